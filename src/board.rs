@@ -1,15 +1,26 @@
 use std::iter::zip;
 
+use crate::limits::{SearchLimits, TimeManager};
 use crate::piece::{Color, Piece, PieceIndex, Type};
+use crate::score::Score;
 // use crate::slotvec::StaticSlotVec;
+use crate::tt::{self, Bound, TableStats, TranspositionTable};
 use crate::utils::Position;
+use crate::zobrist;
 use rand::rngs::ThreadRng;
-use std::cmp::Ordering;
 use std::io::Write;
 use std::ops::{Neg, Shl};
 use std::process::Output;
 use termcolor::{ColorChoice, ColorSpec, WriteColor};
-use std::sync::mpsc::{SyncSender, Receiver};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+
+
+const ROOK_DIRECTIONS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRECTIONS: [(i8, i8); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+const PROMOTION_TYPES: [Type; 4] = [Type::Queen, Type::Rook, Type::Bishop, Type::Knight];
+const KNIGHT_OFFSETS: [(i8, i8); 8] = [(1, 2), (-1, 2), (-2, 1), (-2, -1), (-1, -2), (1, -2), (2, -1), (2, 1)];
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Move {
@@ -17,41 +28,15 @@ pub struct Move {
     pub action: Action,
 }
 
-impl Into<MoveNode> for Move {
-    fn into(self) -> MoveNode {
-        MoveNode {
-            m: self,
-            children: Vec::new(),
-            visited: false,
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct MoveNode {
-    pub m: Move,
-    pub children: Vec<MoveNode>,
-    // no value if not visited yet
-    pub visited: bool,
-}
-
-impl MoveNode {
-    pub fn recursive_children_count(&self) -> usize {
-        self.children.len()
-            + self.children
-            .iter()
-            .map(|c| c.recursive_children_count())
-            .sum::<usize>()
-    }
-}
-
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum Action {
     Move { from: Piece, to: Piece },
     Capture { piece: Piece, target: Piece },
     Promote { old_piece: Piece, new_piece: Piece },
-    CastleKingSide,
-    CastleQueenSide,
+    CapturePromote { old_piece: Piece, new_piece: Piece, target: Piece },
+    EnPassant { piece: Piece, to: Position, target: Piece },
+    CastleKingSide { color: Color },
+    CastleQueenSide { color: Color },
     NoAction,
 }
 
@@ -61,12 +46,50 @@ impl Move {
             Action::Move { .. } => 0,
             Action::Capture { piece, target } => target.value() / 10 - piece.value() / 40,
             Action::Promote { new_piece, .. } => new_piece.value() / 10,
-            Action::CastleKingSide => 20,
-            Action::CastleQueenSide => 10,
+            Action::CapturePromote { new_piece, target, .. } => target.value() / 10 + new_piece.value() / 10,
+            Action::EnPassant { piece, target, .. } => target.value() / 10 - piece.value() / 40,
+            Action::CastleKingSide { .. } => 20,
+            Action::CastleQueenSide { .. } => 10,
             Action::NoAction => 0,
         }
     }
 
+    pub fn captured(&self) -> Option<Piece> {
+        match self.action {
+            Action::Capture { target, .. } | Action::CapturePromote { target, .. } => Some(target),
+            Action::EnPassant { target, .. } => Some(target),
+            _ => None,
+        }
+    }
+
+    // most valuable victim, least valuable attacker: captures and promotions ordered by
+    // the material they gain, then by the piece they risk
+    pub fn mvv_lva(&self) -> i32 {
+        let (attacker, gain) = match self.action {
+            Action::Capture { piece, target } | Action::EnPassant { piece, target, .. } => (piece, target.base_value()),
+            Action::Promote { old_piece, new_piece } => (old_piece, new_piece.base_value()),
+            Action::CapturePromote { old_piece, new_piece, target } => {
+                (old_piece, target.base_value() + new_piece.base_value())
+            }
+            _ => return 0,
+        };
+        10 * gain as i32 - attacker.base_value() as i32
+    }
+
+    pub fn is_capture(&self) -> bool {
+        matches!(
+            self.action,
+            Action::Capture { .. } | Action::CapturePromote { .. } | Action::EnPassant { .. }
+        )
+    }
+
+    pub fn promotion(&self) -> Option<Type> {
+        match self.action {
+            Action::Promote { new_piece, .. } | Action::CapturePromote { new_piece, .. } => Some(new_piece.t),
+            _ => None,
+        }
+    }
+
     pub fn capture_piece(piece: Piece, target: Piece) -> Self {
         Self {
             score: 0,
@@ -99,6 +122,37 @@ impl Move {
         }
     }
 
+    pub fn promote_capture(piece: Piece, target: Piece, t: Type) -> Self {
+        Self {
+            score: 0,
+            action: Action::CapturePromote {
+                old_piece: piece,
+                new_piece: Piece {
+                    t,
+                    position: target.position,
+                    index: piece.index,
+                    color: piece.color,
+                },
+                target,
+            },
+        }
+    }
+
+    pub fn en_passant(piece: Piece, to: Position, target: Piece) -> Self {
+        Self {
+            score: 0,
+            action: Action::EnPassant { piece, to, target },
+        }
+    }
+
+    // passes the turn, only made by the search for null-move pruning
+    pub fn null() -> Self {
+        Self {
+            score: 0,
+            action: Action::NoAction,
+        }
+    }
+
     // pub fn promote_piece(from: Piece, to: Position, t: Type) -> Self {
     //     let piece = Piece {
     //         t,
@@ -119,6 +173,30 @@ impl Move {
     }
 }
 
+// long algebraic notation as used by UCI, e.g. "e2e4", "e7e8q" or "e1g1"
+impl std::fmt::Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.action {
+            Action::Move { from, to } => write!(f, "{}{}", from.position, to.position),
+            Action::Capture { piece, target } => write!(f, "{}{}", piece.position, target.position),
+            Action::Promote { old_piece, new_piece } | Action::CapturePromote { old_piece, new_piece, .. } => {
+                let t = new_piece.t.character().to_ascii_lowercase();
+                write!(f, "{}{}{}", old_piece.position, new_piece.position, t)
+            }
+            Action::EnPassant { piece, to, .. } => write!(f, "{}{}", piece.position, to),
+            Action::CastleKingSide { color } => {
+                let rank = color.home_rank();
+                write!(f, "{}{}", Position::new(4, rank), Position::new(6, rank))
+            }
+            Action::CastleQueenSide { color } => {
+                let rank = color.home_rank();
+                write!(f, "{}{}", Position::new(4, rank), Position::new(2, rank))
+            }
+            Action::NoAction => write!(f, "0000"),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Cell {
     pub piece: Option<Piece>,
@@ -136,6 +214,272 @@ impl Cell {
     }
 }
 
+// irreversible part of the position, saved before each move so it can be restored
+#[derive(Copy, Clone)]
+struct BoardState {
+    en_passant: Option<Position>,
+    halfmove_clock: u32,
+    castling_rights: CastlingRights,
+    key: u64,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CastlingRights {
+    pub white_king_side: bool,
+    pub white_queen_side: bool,
+    pub black_king_side: bool,
+    pub black_queen_side: bool,
+}
+
+impl CastlingRights {
+    pub fn none() -> Self {
+        Self {
+            white_king_side: false,
+            white_queen_side: false,
+            black_king_side: false,
+            black_queen_side: false,
+        }
+    }
+
+    pub fn king_side(&self, color: Color) -> bool {
+        match color {
+            Color::White => self.white_king_side,
+            Color::Black => self.black_king_side,
+        }
+    }
+
+    pub fn queen_side(&self, color: Color) -> bool {
+        match color {
+            Color::White => self.white_queen_side,
+            Color::Black => self.black_queen_side,
+        }
+    }
+
+    // anything leaving or arriving on a king or rook home cell loses the matching rights
+    fn revoke(&mut self, position: Position) {
+        match (position.x, position.y) {
+            (4, 0) => {
+                self.white_king_side = false;
+                self.white_queen_side = false;
+            }
+            (7, 0) => self.white_king_side = false,
+            (0, 0) => self.white_queen_side = false,
+            (4, 7) => {
+                self.black_king_side = false;
+                self.black_queen_side = false;
+            }
+            (7, 7) => self.black_king_side = false,
+            (0, 7) => self.black_queen_side = false,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GameResult {
+    WhiteWins,
+    BlackWins,
+    Draw,
+}
+
+// as written in PGN and xboard result commands
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameResult::WhiteWins => write!(f, "1-0"),
+            GameResult::BlackWins => write!(f, "0-1"),
+            GameResult::Draw => write!(f, "1/2-1/2"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DrawReason {
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate { winner: Color },
+    Stalemate,
+    Draw(DrawReason),
+}
+
+impl GameStatus {
+    // draws by rule can be claimed but the position can still be played on
+    pub fn has_legal_moves(&self) -> bool {
+        !matches!(self, GameStatus::Checkmate { .. } | GameStatus::Stalemate)
+    }
+
+    pub fn result(&self) -> Option<GameResult> {
+        match self {
+            GameStatus::Ongoing => None,
+            GameStatus::Checkmate { winner: Color::White } => Some(GameResult::WhiteWins),
+            GameStatus::Checkmate { winner: Color::Black } => Some(GameResult::BlackWins),
+            GameStatus::Stalemate | GameStatus::Draw(_) => Some(GameResult::Draw),
+        }
+    }
+}
+
+impl std::fmt::Display for GameStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameStatus::Ongoing => write!(f, "Ongoing"),
+            GameStatus::Checkmate { winner: Color::White } => write!(f, "Checkmate, white wins"),
+            GameStatus::Checkmate { winner: Color::Black } => write!(f, "Checkmate, black wins"),
+            GameStatus::Stalemate => write!(f, "Stalemate"),
+            GameStatus::Draw(DrawReason::FiftyMoveRule) => write!(f, "Draw by the fifty-move rule"),
+            GameStatus::Draw(DrawReason::ThreefoldRepetition) => write!(f, "Draw by threefold repetition"),
+            GameStatus::Draw(DrawReason::InsufficientMaterial) => write!(f, "Draw by insufficient material"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FenError {
+    MissingField(&'static str),
+    PiecePlacement(String),
+    SideToMove(String),
+    CastlingRights(String),
+    EnPassant(String),
+    HalfmoveClock(String),
+    FullmoveNumber(String),
+}
+
+impl std::fmt::Display for FenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FenError::MissingField(field) => write!(f, "missing {} field", field),
+            FenError::PiecePlacement(reason) => write!(f, "invalid piece placement: {}", reason),
+            FenError::SideToMove(value) => write!(f, "invalid side to move '{}'", value),
+            FenError::CastlingRights(value) => write!(f, "invalid castling rights '{}'", value),
+            FenError::EnPassant(value) => write!(f, "invalid en passant square '{}'", value),
+            FenError::HalfmoveClock(value) => write!(f, "invalid halfmove clock '{}'", value),
+            FenError::FullmoveNumber(value) => write!(f, "invalid fullmove number '{}'", value),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SearchResult {
+    BestMove(Move),
+    GameOver(GameStatus),
+}
+
+// state of the search after each completed iteration
+#[derive(Clone, Debug)]
+pub struct SearchReport {
+    pub depth: i32,
+    // deepest ply reached, captures included
+    pub seldepth: usize,
+    pub score: Score,
+    pub nodes: u64,
+    // the part of nodes spent in the quiescence search
+    pub qnodes: u64,
+    pub time: std::time::Duration,
+    // permille of the transposition table in use
+    pub hashfull: u32,
+    // transposition table use since the search started
+    pub tt: TableStats,
+    pub pv: Vec<Move>,
+}
+
+impl SearchReport {
+    pub fn nps(&self) -> u64 {
+        (self.nodes as f64 / self.time.as_secs_f64().max(0.001)) as u64
+    }
+}
+
+const MAX_PLY: usize = 128;
+// half width of the first window around the previous iteration's score
+const ASPIRATION_WINDOW: i16 = 25;
+// margin per ply of depth by which the static evaluation has to beat beta for reverse futility pruning
+const REVERSE_FUTILITY_MARGIN: i16 = 80;
+// margins by which the static evaluation has to miss alpha for razoring, by depth
+const RAZORING_MARGINS: [i16; 4] = [0, 250, 400, 550];
+// positional swing allowed on top of the captured piece before delta pruning skips a capture
+const DELTA_MARGIN: i16 = 200;
+
+// selective search techniques, all enabled by default, switching one off
+// shows what it's worth in nodes and strength
+#[derive(Copy, Clone, Debug)]
+pub struct Pruning {
+    pub null_move: bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility: bool,
+    pub razoring: bool,
+    // captures in the quiescence search that can't raise alpha
+    pub delta: bool,
+    // quiet moves giving check are searched at the first ply of the quiescence search,
+    // which finds more mates and tactics for a lot more nodes
+    pub quiet_checks: bool,
+}
+
+impl Default for Pruning {
+    fn default() -> Self {
+        Self {
+            null_move: true,
+            late_move_reductions: true,
+            reverse_futility: true,
+            razoring: true,
+            delta: true,
+            quiet_checks: false,
+        }
+    }
+}
+// history scores stay within this range, below the priority of captures when ordering moves
+const HISTORY_LIMIT: i32 = 500_000;
+
+// move ordering and principal variation of a running search, allocated up front
+// so memory doesn't grow with the depth or the length of the search. The ordering
+// tables start empty for every search, what they learnt about the previous position
+// is as likely to mislead as to help after two moves were played.
+struct SearchContext<'a> {
+    // set by the caller to end the search early
+    stop: &'a AtomicBool,
+    tt: &'a mut TranspositionTable,
+    // last two quiet moves per ply that caused a beta cutoff
+    killers: [[Option<Move>; 2]; MAX_PLY],
+    // quiet moves by [color][from][to], raised when they cause a beta cutoff and
+    // lowered when they were searched before the move that did
+    history: Box<[[[i32; 64]; 64]; 2]>,
+    // quiet move that refuted the previous move, by that move's [from][to]
+    countermoves: Box<[[Option<Move>; 64]; 64]>,
+    // triangular pv table, pv[ply] is the best line found from that ply
+    pv: Vec<Vec<Move>>,
+    seldepth: usize,
+    time: TimeManager,
+    max_nodes: Option<u64>,
+}
+
+impl<'a> SearchContext<'a> {
+    fn new(stop: &'a AtomicBool, tt: &'a mut TranspositionTable, time: TimeManager, max_nodes: Option<u64>) -> Self {
+        Self {
+            stop,
+            tt,
+            killers: [[None; 2]; MAX_PLY],
+            history: Box::new([[[0; 64]; 64]; 2]),
+            countermoves: Box::new([[None; 64]; 64]),
+            pv: (0..=MAX_PLY).map(|_| Vec::with_capacity(MAX_PLY)).collect(),
+            seldepth: 0,
+            time,
+            max_nodes,
+        }
+    }
+
+    fn update_pv(&mut self, ply: usize, m: Move) {
+        let (line, rest) = self.pv.split_at_mut(ply + 1);
+        line[ply].clear();
+        line[ply].push(m);
+        line[ply].extend_from_slice(&rest[0]);
+    }
+}
+
 #[derive(Debug)]
 pub enum Command {
     Stop,
@@ -147,8 +491,9 @@ pub enum Command {
 #[derive(Debug)]
 pub enum Response {
     Ack,
+    Report(SearchReport),
     FoundMove(Move),
-    NoValidMove,
+    GameOver(GameStatus),
 }
 
 #[derive(Clone)]
@@ -161,6 +506,16 @@ pub struct Board {
 
     cells: [[Cell; 8]; 8],
     move_stack: Vec<Move>,
+    state_stack: Vec<BoardState>,
+
+    // square behind a pawn that just moved two cells
+    en_passant: Option<Position>,
+    // plies since the last capture or pawn move
+    halfmove_clock: u32,
+    // zobrist key of the current position, updated incrementally
+    key: u64,
+    // key of the position before each move of the move stack
+    key_history: Vec<u64>,
 
     green: ColorSpec,
     red: ColorSpec,
@@ -169,16 +524,18 @@ pub struct Board {
     black_piece_count: i8,
 
     move_count: i64,
-    white_king_move_count: i32,
-    white_king_rook_move_count: i32,
-    white_queen_rook_move_count: i32,
+    // moves made in the quiescence search, part of move_count
+    qnode_count: u64,
+    castling_rights: CastlingRights,
 
-    black_king_move_count: i32,
-    black_king_rook_move_count: i32,
-    black_queen_rook_move_count: i32,
+    side_to_move: Color,
+    fullmove_number: u32,
 
-    root_node: Option<MoveNode>,
+    root_ply: usize,
+    // shared between clones, so a front end can keep it across searches
+    tt: Option<Arc<Mutex<TranspositionTable>>>,
     should_stop: bool,
+    pruning: Pruning,
 
     // stats
     evaluate_position_calls: u64,
@@ -197,6 +554,11 @@ impl Board {
             black_pieces: [None; 16],
             cells: [[Cell::empty(); 8]; 8],
             move_stack: Vec::new(),
+            state_stack: Vec::new(),
+            en_passant: None,
+            halfmove_clock: 0,
+            key: zobrist::castling(&CastlingRights::none()),
+            key_history: Vec::new(),
             green,
             red,
             used_black_pieces: 0,
@@ -206,16 +568,16 @@ impl Board {
             black_piece_count: 0,
 
             move_count: 0,
-            white_king_move_count: 0,
-            white_king_rook_move_count: 0,
-            white_queen_rook_move_count: 0,
+            qnode_count: 0,
+            castling_rights: CastlingRights::none(),
 
-            black_king_move_count: 0,
-            black_king_rook_move_count: 0,
-            black_queen_rook_move_count: 0,
+            side_to_move: Color::White,
+            fullmove_number: 1,
 
-            root_node: Some(Move { score: 0, action: Action::NoAction }.into()),
+            root_ply: 0,
+            tt: None,
             should_stop: false,
+            pruning: Pruning::default(),
 
             evaluate_position_calls: 0,
         }
@@ -242,7 +604,7 @@ impl Board {
             return None;
         }
 
-        let legal_moves = self.collect_all_moves(self.current_color(), false, false);
+        let legal_moves = self.collect_legal_moves(false);
 
         let p1 = Position::new(x1, y1);
         let p2 = Position::new(x2, y2);
@@ -250,41 +612,46 @@ impl Board {
         let color = self.current_color();
 
         for m in legal_moves {
-            match m.m.action {
+            match m.action {
                 Action::Move { from, to } => {
                     if from.position == p1 && to.position == p2 {
-                        return Some(m.m);
+                        return Some(m);
                     }
                 }
                 Action::Capture { piece, target } => {
                     if piece.position == p1 && target.position == p2 {
-                        return Some(m.m);
+                        return Some(m);
                     }
                 }
-                Action::Promote { old_piece, new_piece } => {
+                Action::Promote { old_piece, new_piece } | Action::CapturePromote { old_piece, new_piece, .. } => {
                     if old_piece.position == p1 && new_piece.position == p2 {
-                        return Some(m.m);
+                        return Some(m);
+                    }
+                }
+                Action::EnPassant { piece, to, .. } => {
+                    if piece.position == p1 && to == p2 {
+                        return Some(m);
                     }
                 }
-                Action::CastleKingSide => {
+                Action::CastleKingSide { .. } => {
                     if color == Color::White {
                         if p1 == Position::new(4, 0) && p2 == Position::new(6, 0) {
-                            return Some(m.m);
+                            return Some(m);
                         }
                     } else {
                         if p1 == Position::new(4, 7) && p2 == Position::new(6, 7) {
-                            return Some(m.m);
+                            return Some(m);
                         }
                     }
                 }
-                Action::CastleQueenSide => {
+                Action::CastleQueenSide { .. } => {
                     if color == Color::White {
                         if p1 == Position::new(4, 0) && p2 == Position::new(2, 0) {
-                            return Some(m.m);
+                            return Some(m);
                         }
                     } else {
                         if p1 == Position::new(4, 7) && p2 == Position::new(2, 7) {
-                            return Some(m.m);
+                            return Some(m);
                         }
                     }
                 }
@@ -295,21 +662,6 @@ impl Board {
         return None;
     }
 
-    pub fn parse_move(&mut self, msg: &String) -> bool {
-        let msg = msg.as_bytes();
-        if msg.len() < 4 {
-            return false;
-        }
-        let char_to_n = |c: u8| c as i8 - 'a' as i8;
-        let digit_to_n = |c: u8| c as i8 - '1' as i8;
-        let x1 = char_to_n(msg[0]);
-        let y1 = digit_to_n(msg[1]);
-        let x2 = char_to_n(msg[2]);
-        let y2 = digit_to_n(msg[3]);
-
-        self.move_from_position(x1, y1, x2, y2).is_some()
-    }
-
     pub fn new_promote_game() -> Self {
         let mut game = Board::new_empty_game();
 
@@ -357,6 +709,7 @@ impl Board {
         game.add_new_piece(Color::Black, Type::Knight, 1, 7);
         game.add_new_piece(Color::Black, Type::Knight, 6, 7);
 
+        game.grant_castling_rights();
         game
     }
 
@@ -412,6 +765,170 @@ impl Board {
         game
     }
 
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let mut fields = fen.split_whitespace();
+        let placement = fields.next().ok_or(FenError::MissingField("piece placement"))?;
+        let side = fields.next().ok_or(FenError::MissingField("side to move"))?;
+        let castling = fields.next().ok_or(FenError::MissingField("castling rights"))?;
+        let en_passant = fields.next().ok_or(FenError::MissingField("en passant"))?;
+        // the move counters are often left out, e.g. in EPD records
+        let halfmove_clock = fields.next().unwrap_or("0");
+        let fullmove_number = fields.next().unwrap_or("1");
+
+        let mut game = Board::new_empty_game();
+
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != 8 {
+            return Err(FenError::PiecePlacement(format!("expected 8 ranks, found {}", ranks.len())));
+        }
+
+        for (i, rank) in ranks.iter().enumerate() {
+            let y = 7 - i as i8;
+            let mut x: i8 = 0;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    // x is at most 8 here, adding a single digit can't overflow
+                    x += empty as i8;
+                    if x > 8 {
+                        return Err(FenError::PiecePlacement(format!("rank {} has more than 8 cells", y + 1)));
+                    }
+                    continue;
+                }
+
+                let t = Type::from_char(c)
+                    .ok_or_else(|| FenError::PiecePlacement(format!("unknown piece '{}'", c)))?;
+                let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+                if x > 7 {
+                    return Err(FenError::PiecePlacement(format!("rank {} has more than 8 cells", y + 1)));
+                }
+
+                let used = match color {
+                    Color::White => game.used_white_pieces,
+                    Color::Black => game.used_black_pieces,
+                };
+                if used == 16 {
+                    return Err(FenError::PiecePlacement(format!("more than 16 {:?} pieces", color)));
+                }
+
+                game.add_new_piece(color, t, x, y);
+                x += 1;
+            }
+
+            if x != 8 {
+                return Err(FenError::PiecePlacement(format!("rank {} does not have 8 cells", y + 1)));
+            }
+        }
+
+        game.side_to_move = match side {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(FenError::SideToMove(side.to_string())),
+        };
+
+        game.castling_rights = CastlingRights::none();
+        if castling != "-" {
+            for c in castling.chars() {
+                match c {
+                    'K' => game.castling_rights.white_king_side = true,
+                    'Q' => game.castling_rights.white_queen_side = true,
+                    'k' => game.castling_rights.black_king_side = true,
+                    'q' => game.castling_rights.black_queen_side = true,
+                    _ => return Err(FenError::CastlingRights(castling.to_string())),
+                }
+            }
+        }
+
+        if en_passant != "-" {
+            let expected_rank = if game.side_to_move == Color::White { 5 } else { 2 };
+            match Position::parse(en_passant) {
+                Some(position) if position.y == expected_rank => game.en_passant = Some(position),
+                _ => return Err(FenError::EnPassant(en_passant.to_string())),
+            }
+        }
+
+        game.halfmove_clock = halfmove_clock
+            .parse()
+            .map_err(|_| FenError::HalfmoveClock(halfmove_clock.to_string()))?;
+
+        game.fullmove_number = match fullmove_number.parse() {
+            Ok(n) if n >= 1 => n,
+            _ => return Err(FenError::FullmoveNumber(fullmove_number.to_string())),
+        };
+
+        game.key = game.compute_key();
+        Ok(game)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+
+        for y in (0..8).rev() {
+            let mut empty = 0;
+            for x in 0..8 {
+                match self.piece_at(&Position::new(x, y)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push_str(piece.character());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                fen.push('/');
+            }
+        }
+
+        fen.push_str(if self.current_color() == Color::White { " w " } else { " b " });
+
+        let rights = self.castling_rights;
+        let mut castling = String::new();
+        for (allowed, c) in [
+            (rights.white_king_side, 'K'),
+            (rights.white_queen_side, 'Q'),
+            (rights.black_king_side, 'k'),
+            (rights.black_queen_side, 'q'),
+        ] {
+            if allowed {
+                castling.push(c);
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        match self.en_passant {
+            Some(position) => fen.push_str(&format!(" {}", position)),
+            None => fen.push_str(" -"),
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove_clock, self.fullmove_number()));
+        fen
+    }
+
+    // rights for every king and rook on their initial cells, for boards set up piece by piece
+    fn grant_castling_rights(&mut self) {
+        let is_at = |x, color: Color, t| {
+            matches!(self.piece_at(&Position::new(x, color.home_rank())), Some(p) if p.color == color && p.t == t)
+        };
+        let can_castle = |color, rook_x| is_at(4, color, Type::King) && is_at(rook_x, color, Type::Rook);
+        let rights = CastlingRights {
+            white_king_side: can_castle(Color::White, 7),
+            white_queen_side: can_castle(Color::White, 0),
+            black_king_side: can_castle(Color::Black, 7),
+            black_queen_side: can_castle(Color::Black, 0),
+        };
+
+        self.key ^= zobrist::castling(&self.castling_rights) ^ zobrist::castling(&rights);
+        self.castling_rights = rights;
+    }
+
     pub fn add_new_piece(&mut self, color: Color, t: Type, x: i8, y: i8) {
         if color == Color::White {
             self.white_pieces[self.used_white_pieces] = Some(Piece {
@@ -421,6 +938,7 @@ impl Board {
                 index: self.used_white_pieces as u8,
             });
             self.cells[x as usize][y as usize].piece = self.white_pieces[self.used_white_pieces];
+            self.key ^= zobrist::piece(&self.white_pieces[self.used_white_pieces].unwrap());
             self.used_white_pieces += 1;
         } else {
             self.black_pieces[self.used_black_pieces] = Some(Piece {
@@ -430,139 +948,610 @@ impl Board {
                 index: self.used_black_pieces as u8,
             });
             self.cells[x as usize][y as usize].piece = self.black_pieces[self.used_black_pieces];
+            self.key ^= zobrist::piece(&self.black_pieces[self.used_black_pieces].unwrap());
             self.used_black_pieces += 1;
         }
     }
 
-    pub fn find_best_move(&mut self, depth: i32, rx: &Receiver<Command>) -> Option<Move> {
-        self.move_count = 0;
-        let t1 = std::time::Instant::now();
+    pub fn game_status(&self) -> GameStatus {
+        let color = self.current_color();
+        if self.collect_legal_moves(false).is_empty() {
+            if self.is_in_check(color) {
+                GameStatus::Checkmate { winner: color.opposite() }
+            } else {
+                GameStatus::Stalemate
+            }
+        } else if let Some(reason) = self.draw_reason() {
+            GameStatus::Draw(reason)
+        } else {
+            GameStatus::Ongoing
+        }
+    }
+
+    pub fn draw_reason(&self) -> Option<DrawReason> {
+        if self.halfmove_clock >= 100 {
+            Some(DrawReason::FiftyMoveRule)
+        } else if self.repetition_count() >= 2 {
+            Some(DrawReason::ThreefoldRepetition)
+        } else if self.has_insufficient_material() {
+            Some(DrawReason::InsufficientMaterial)
+        } else {
+            None
+        }
+    }
+
+    pub fn halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    // number of earlier occurrences of the current position
+    pub fn repetition_count(&self) -> usize {
+        self.key_history
+            .iter()
+            .rev()
+            .take(self.halfmove_clock as usize)
+            .skip(1)
+            .step_by(2)
+            .filter(|k| **k == self.key)
+            .count()
+    }
+
+    // without pieces other than pawns zugzwang is common, passing would often be the best move
+    fn has_non_pawn_material(&self, color: Color) -> bool {
+        let pieces = match color {
+            Color::White => &self.white_pieces,
+            Color::Black => &self.black_pieces,
+        };
+        pieces.iter().flatten().any(|piece| !matches!(piece.t, Type::Pawn | Type::King))
+    }
 
-        if self.root_node.is_none() {
-            self.root_node = Some(Move { score: 0, action: Action::NoAction }.into());
+    // K vs K, KB vs K and KN vs K can never end in checkmate
+    pub fn has_insufficient_material(&self) -> bool {
+        let mut minor_pieces = 0;
+        for piece in self.white_pieces.iter().chain(self.black_pieces.iter()).flatten() {
+            match piece.t {
+                Type::King => {}
+                Type::Bishop | Type::Knight => minor_pieces += 1,
+                _ => return false,
+            }
         }
-        let mut root_node = self.root_node.take().unwrap();
+        minor_pieces <= 1
+    }
+
+    pub fn key(&self) -> u64 {
+        self.key
+    }
 
+    // zobrist key computed from scratch, the incremental one must always match it
+    fn compute_key(&self) -> u64 {
+        let mut key = 0;
+        for piece in self.white_pieces.iter().chain(self.black_pieces.iter()).flatten() {
+            key ^= zobrist::piece(piece);
+        }
+        key ^= zobrist::castling(&self.castling_rights);
+        key ^= zobrist::en_passant(self.en_passant);
+        if self.side_to_move == Color::Black {
+            key ^= zobrist::side();
+        }
+        key
+    }
+
+    // `report` is called after every completed iteration of the iterative deepening. A drawn
+    // position that still has legal moves is searched, claiming the draw is up to the caller.
+    pub fn find_best_move(
+        &mut self,
+        limits: &SearchLimits,
+        stop: &AtomicBool,
+        mut report: impl FnMut(&SearchReport),
+    ) -> SearchResult {
+        let status = self.game_status();
+        if !status.has_legal_moves() {
+            wait_for_stop(limits, stop);
+            return SearchResult::GameOver(status);
+        }
+
+        self.move_count = 0;
+        self.qnode_count = 0;
+        self.root_ply = self.move_stack.len();
         self.should_stop = false;
         self.evaluate_position_calls = 0;
+        let time = TimeManager::new(limits, self.current_color());
+
+        let tt = self.transposition_table();
+        let mut tt = tt.lock().unwrap();
+        tt.reset_stats();
+        let mut context = SearchContext::new(stop, &mut tt, time, limits.nodes);
+
+        let mut best_line = Vec::new();
+        let mut previous_score = None;
+        for i_depth in 1..=limits.max_depth() {
+            if i_depth > 1 && !context.time.can_start_iteration() {
+                break;
+            }
 
-        for i_depth in 1..depth {
-            self.search(
-                i_depth,
-                -i16::MAX,
-                i16::MAX,
-                &mut root_node,
-                false,
-                rx,
-            );
+            context.seldepth = 0;
+            let score = self.aspiration_search(&mut context, i_depth, previous_score);
 
+            // an interrupted iteration didn't look at every move, keep the previous one
             if self.should_stop {
                 break;
             }
+            best_line = context.pv[0].clone();
+            previous_score = Some(score);
+
+            let iteration = SearchReport {
+                depth: i_depth,
+                seldepth: context.seldepth,
+                score,
+                nodes: self.move_count as u64,
+                qnodes: self.qnode_count,
+                time: context.time.elapsed(),
+                hashfull: context.tt.hashfull(),
+                tt: context.tt.stats(),
+                pv: best_line.clone(),
+            };
+            report(&iteration);
+
+            if let (Some(mate), Some(moves)) = (limits.mate, score.mate_moves()) {
+                if moves > 0 && moves as u32 <= mate {
+                    break;
+                }
+            }
         }
 
-        let t2 = std::time::Instant::now();
+        // the search may have been stopped before the first iteration completed
+        let best_move = best_line.first().copied().unwrap_or_else(|| self.collect_legal_moves(false)[0]);
+        wait_for_stop(limits, stop);
+        SearchResult::BestMove(best_move)
+    }
 
-        let mut best_move = None;
-        let mut best_score = -i16::MAX;
+    // moves made during the last search
+    pub fn node_count(&self) -> i64 {
+        self.move_count
+    }
+
+    pub fn qnode_count(&self) -> u64 {
+        self.qnode_count
+    }
+
+    // the table is only allocated once it's needed
+    pub fn transposition_table(&mut self) -> Arc<Mutex<TranspositionTable>> {
+        self.tt
+            .get_or_insert_with(|| Arc::new(Mutex::new(TranspositionTable::new(tt::DEFAULT_SIZE_MB))))
+            .clone()
+    }
+
+    pub fn set_transposition_table(&mut self, tt: Arc<Mutex<TranspositionTable>>) {
+        self.tt = Some(tt);
+    }
+
+    pub fn set_hash_size(&mut self, megabytes: usize) {
+        self.transposition_table().lock().unwrap().resize(megabytes);
+    }
 
-        for child in &root_node.children {
-            // println!("child: {:?}", child.m);
-            if child.m.score > best_score {
-                best_score = child.m.score;
-                best_move = Some(child.m);
+    pub fn pruning(&self) -> Pruning {
+        self.pruning
+    }
+
+    pub fn set_pruning(&mut self, pruning: Pruning) {
+        self.pruning = pruning;
+    }
+
+    // the score rarely moves much between iterations, so a narrow window around the
+    // previous one cuts more, and is widened whenever the score falls outside of it
+    fn aspiration_search(&mut self, context: &mut SearchContext, depth: i32, previous_score: Option<Score>) -> Score {
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = match previous_score {
+            Some(score) if depth >= 4 && !score.is_mate() => (score - delta, score + delta),
+            _ => (-Score::INFINITE, Score::INFINITE),
+        };
+
+        loop {
+            let score = self.search(context, depth, alpha, beta);
+            if self.should_stop {
+                return score;
             }
-        }
 
-        self.root_node = Some(root_node);
+            if score <= alpha {
+                alpha = score - delta;
+            } else if score >= beta {
+                beta = score + delta;
+            } else {
+                return score;
+            }
 
-        println!("score = {}, move count = {}, positions = {}, time = {:?}", best_score, self.move_count, self.evaluate_position_calls, t2 - t1);
-        return best_move;
+            delta = delta.saturating_mul(2);
+            if delta > 400 {
+                alpha = -Score::INFINITE;
+                beta = Score::INFINITE;
+            }
+        }
     }
 
-    pub fn search(
-        &mut self,
-        depth: i32,
-        mut alpha: i16,
-        beta: i16,
-        parent: &mut MoveNode,
-        only_captures: bool,
-        rx: &Receiver<Command>,
-    ) -> i16 {
-        if rx.try_recv().is_ok() {
+    // sets should_stop once the caller, the clock or the node limit ends the search
+    fn check_stop(&mut self, context: &SearchContext) -> bool {
+        if context.stop.load(Ordering::Relaxed) {
             self.should_stop = true;
         }
+        // reading the clock at every node would be noticeably slower
+        if self.move_count % 1024 == 0 && context.time.out_of_time() {
+            self.should_stop = true;
+        }
+        if context.max_nodes.map_or(false, |nodes| self.move_count as u64 >= nodes) {
+            self.should_stop = true;
+        }
+        self.should_stop
+    }
+
+    fn search(&mut self, context: &mut SearchContext, depth: i32, mut alpha: Score, mut beta: Score) -> Score {
+        if self.check_stop(context) {
+            return Score::DRAW;
+        }
+
+        let ply = self.move_stack.len() - self.root_ply;
+        context.pv[ply].clear();
+        context.seldepth = context.seldepth.max(ply);
 
-        if let Action::Capture { target, .. } = &parent.m.action {
-            if target.t == Type::King {
-                return self.evaluate_position();
+        // a position seen before can be repeated again by either side, so a single
+        // repetition inside the tree is already scored as a draw
+        if ply > 0 {
+            if self.halfmove_clock >= 100 || self.repetition_count() >= 1 || self.has_insufficient_material() {
+                return Score::DRAW;
+            }
+
+            // no line from here can do better than mating right away, or worse than being mated now
+            alpha = alpha.max(Score::mated_in(ply));
+            beta = beta.min(Score::mate_in(ply + 1));
+            if alpha >= beta {
+                return alpha;
             }
         }
 
-        if depth == 0 && !only_captures {
-            // return self.evaluate_position();
-            return self.search(depth - 1, alpha, beta, parent, true, &rx);
+        // draws and mate distance are decided above, the quiescence search doesn't look at them
+        if depth <= 0 {
+            return self.quiescence(context, 0, alpha, beta);
         }
 
-        // if depth == 0 {
-        //     return Move::evaluate(self.evaluate_position());
-        // }
+        if ply >= MAX_PLY - 1 {
+            return Score::centipawns(self.evaluate_position());
+        }
+
+        let mut tt_move = None;
+        if let Some(entry) = context.tt.probe(self.key) {
+            tt_move = entry.best_move;
+            // the root always searches, it has to come up with a move
+            if ply > 0 && entry.depth as i32 >= depth {
+                let score = entry.score.from_tt(ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        let in_check = self.is_in_check(self.current_color());
+        // only at nodes searched with a null window, a wrong guess in the principal variation costs too much
+        let pv_node = alpha + 1 < beta;
+        if ply > 0 && !in_check && !pv_node {
+            if let Some(score) = self.prune(context, depth, alpha, beta) {
+                return score;
+            }
+        }
 
-        if only_captures && depth < -10 {
-            // return Move{ score: 0, action: Action::Evaluation {score: self.evaluate_position()}};
-            return self.evaluate_position();
+        let mut moves = self.collect_legal_moves(false);
+        if moves.is_empty() {
+            // checkmate or stalemate, prefer the shortest mate
+            return if in_check { Score::mated_in(ply) } else { Score::DRAW };
         }
 
-        let mut tmp_children = Vec::new();
+        self.order_moves(context, &mut moves, tt_move, ply);
 
-        let mut children = if !parent.visited && !self.should_stop {
-            tmp_children = self.collect_all_moves(self.current_color(), only_captures, false);
-            if !only_captures {
-                parent.visited = true;
-                parent.children = tmp_children;
-                &mut parent.children
+        // fail-soft: the returned score can lie outside of the alpha-beta window
+        let mut best_score = -Score::INFINITE;
+        let mut best_move = None;
+        let mut quiets_searched = Vec::new();
+        for (i, m) in moves.into_iter().enumerate() {
+            let quiet = !m.is_capture() && m.promotion().is_none();
+            self.push_move(m);
+            let score = if i == 0 {
+                -self.search(context, depth - 1, -beta, -alpha)
             } else {
-                &mut tmp_children
+                // late quiet moves rarely turn out best, they are searched shallower first
+                // and only get the full depth when they beat alpha anyway
+                let mut score = alpha + 1;
+                if self.pruning.late_move_reductions
+                    && depth >= 3
+                    && i >= 3
+                    && quiet
+                    && !in_check
+                    && !self.is_in_check(self.current_color())
+                {
+                    let reduced_depth = (depth - 1 - late_move_reduction(depth, i)).max(1);
+                    score = -self.search(context, reduced_depth, -(alpha + 1), -alpha);
+                }
+
+                // with good ordering the first move is best, the others only need a
+                // null window to prove they aren't, and get searched again when they are
+                if score > alpha {
+                    score = -self.search(context, depth - 1, -(alpha + 1), -alpha);
+                }
+                if score > alpha && score < beta {
+                    -self.search(context, depth - 1, -beta, -alpha)
+                } else {
+                    score
+                }
+            };
+            self.pop_move();
+
+            // scores of an interrupted search are meaningless
+            if self.should_stop {
+                return Score::DRAW;
+            }
+
+            if score <= best_score {
+                if quiet {
+                    quiets_searched.push(m);
+                }
+                continue;
+            }
+            best_score = score;
+
+            if score >= beta {
+                if quiet {
+                    self.update_quiet_ordering(context, m, &quiets_searched, depth, ply);
+                }
+                context.tt.store(self.key, depth as u8, Bound::Lower, score.to_tt(ply), Some(m));
+                return score;
+            }
+
+            if score > alpha {
+                alpha = score;
+                best_move = Some(m);
+                context.update_pv(ply, m);
+            }
+            if quiet {
+                quiets_searched.push(m);
             }
+        }
+
+        let bound = if best_move.is_some() { Bound::Exact } else { Bound::Upper };
+        context.tt.store(self.key, depth as u8, bound, best_score.to_tt(ply), best_move);
+
+        best_score
+    }
+
+    // searches captures until the position is quiet, so the evaluation isn't taken in the
+    // middle of an exchange. `depth` is 0 at the first ply and negative below it.
+    fn quiescence(&mut self, context: &mut SearchContext, depth: i32, mut alpha: Score, beta: Score) -> Score {
+        if self.check_stop(context) {
+            return Score::DRAW;
+        }
+
+        let ply = self.move_stack.len() - self.root_ply;
+        context.pv[ply].clear();
+        context.seldepth = context.seldepth.max(ply);
+
+        if ply >= MAX_PLY - 1 {
+            return Score::centipawns(self.evaluate_position());
+        }
+
+        let color = self.current_color();
+        let in_check = self.is_in_check(color);
+        let stand_pat = if in_check {
+            -Score::INFINITE
         } else {
-            &mut parent.children
+            Score::centipawns(self.evaluate_position())
         };
 
-        //let mut moves = self.collect_all_moves(self.current_color(), only_captures, false);
-        if children.is_empty() {
-            return self.evaluate_position();
+        let mut moves;
+        let mut best_score;
+        if in_check {
+            // in check there's no standing pat, every evasion has to be looked at
+            moves = self.collect_legal_moves(false);
+            if moves.is_empty() {
+                return Score::mated_in(ply);
+            }
+            best_score = -Score::INFINITE;
+        } else {
+            // the side to move can usually do at least as well as the static evaluation
+            // by not capturing at all
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+            best_score = stand_pat;
+
+            moves = self.collect_legal_moves(true);
+            // a capture that loses material won't improve on standing pat
+            moves.retain(|m| m.promotion().is_some() || !self.is_losing_capture(m));
+
+            if depth == 0 && self.pruning.quiet_checks {
+                let mut checks = self.collect_legal_moves(false);
+                checks.retain(|m| !m.is_capture() && m.promotion().is_none() && self.gives_check(*m));
+                moves.append(&mut checks);
+            }
+        }
+
+        self.order_moves(context, &mut moves, None, ply);
+
+        for m in moves {
+            // even winning the captured piece wouldn't bring the score up to alpha
+            if self.pruning.delta && !in_check && m.promotion().is_none() {
+                if let Some(target) = m.captured() {
+                    if stand_pat + (target.base_value() + DELTA_MARGIN) < alpha {
+                        continue;
+                    }
+                }
+            }
+
+            self.push_move(m);
+            self.qnode_count += 1;
+            let score = -self.quiescence(context, depth - 1, -beta, -alpha);
+            self.pop_move();
+
+            if self.should_stop {
+                return Score::DRAW;
+            }
+
+            if score > best_score {
+                best_score = score;
+                if score >= beta {
+                    return score;
+                }
+                if score > alpha {
+                    alpha = score;
+                    context.update_pv(ply, m);
+                }
+            }
         }
 
-        self.sort_moves(children);
+        best_score
+    }
+
+    // only tries the move, it isn't counted as a node and doesn't enter the move history
+    fn gives_check(&mut self, m: Move) -> bool {
+        self.make_move(m);
+        let check = self.is_in_check(self.current_color());
+        self.unmake_move(m);
+        check
+    }
+
+    // cutoffs at nodes where the side to move is far ahead or behind, decided before
+    // generating moves, None when the node has to be searched normally
+    fn prune(&mut self, context: &mut SearchContext, depth: i32, alpha: Score, beta: Score) -> Option<Score> {
+        let eval = Score::centipawns(self.evaluate_position());
+        let color = self.current_color();
+
+        // so far above beta that the opponent won't allow this position
+        if self.pruning.reverse_futility
+            && depth <= 6
+            && !beta.is_mate()
+            && eval - REVERSE_FUTILITY_MARGIN * depth as i16 >= beta
+        {
+            return Some(eval);
+        }
 
-        // let moves = if only_captures {
-        //     &mut moves
-        // } else {
-        //     parent.children = moves;
-        //     &mut parent.children
-        // };
+        // so far below alpha that only captures could make up for it
+        if self.pruning.razoring && depth <= 3 && eval + RAZORING_MARGINS[depth as usize] < alpha {
+            let score = self.quiescence(context, 0, alpha, beta);
+            if self.should_stop || score <= alpha {
+                return Some(score);
+            }
+        }
 
-        for m in children {
-            self.push_move(m.m);
-            let score = -self.search(depth - 1, -beta, -alpha, m, only_captures, &rx);
+        // passing the turn is worse than any move outside of zugzwang, if the opponent
+        // still can't get below beta after a shallower search the node fails high
+        let after_null_move = matches!(self.last_move().map(|m| m.action), Some(Action::NoAction));
+        if self.pruning.null_move
+            && depth >= 3
+            && eval >= beta
+            && !after_null_move
+            && self.has_non_pawn_material(color)
+        {
+            let reduction = 3 + depth / 6;
+            self.push_move(Move::null());
+            let score = -self.search(context, depth - 1 - reduction, -beta, -(beta - 1));
             self.pop_move();
 
-            m.m.score = score;
+            if self.should_stop {
+                return Some(Score::DRAW);
+            }
+            // a mate found without moving isn't proven
+            if score >= beta {
+                return Some(if score.is_mate() { beta } else { score });
+            }
+        }
+
+        None
+    }
+
+    // hash move first, then promotions and captures that don't lose material, killers,
+    // the countermove, the other quiet moves by history and losing captures last
+    fn order_moves(&self, context: &SearchContext, moves: &mut Vec<Move>, tt_move: Option<Move>, ply: usize) {
+        let color = self.current_color();
+        let countermove = self.previous_move_squares().and_then(|(from, to)| context.countermoves[from][to]);
+        moves.sort_by_cached_key(|m| {
+            let priority = if tt_move.map(|t| t.action) == Some(m.action) {
+                i32::MAX
+            } else if m.promotion().is_some() {
+                3_000_000 + m.mvv_lva()
+            } else if m.is_capture() {
+                if self.is_losing_capture(m) {
+                    -1_000_000 + m.mvv_lva()
+                } else {
+                    3_000_000 + m.mvv_lva()
+                }
+            } else if context.killers[ply][0].map(|k| k.action) == Some(m.action) {
+                2_000_002
+            } else if context.killers[ply][1].map(|k| k.action) == Some(m.action) {
+                2_000_001
+            } else if countermove.map(|c| c.action) == Some(m.action) {
+                2_000_000
+            } else {
+                let history = match self.move_endpoints(m) {
+                    Some((piece, to)) => context.history[color as usize][square_index(piece.position)][square_index(to)],
+                    None => 0,
+                };
+                m.value() as i32 + history
+            };
+            std::cmp::Reverse(priority)
+        });
+    }
 
-            // println!("test move: {}", test_move.evaluation);
+    // a quiet move that caused a beta cutoff is likely to do it again in sibling positions,
+    // the quiet moves searched before it without success are less likely to
+    fn update_quiet_ordering(
+        &self,
+        context: &mut SearchContext,
+        m: Move,
+        quiets_searched: &[Move],
+        depth: i32,
+        ply: usize,
+    ) {
+        let killers = &mut context.killers[ply];
+        if killers[0].map(|k| k.action) != Some(m.action) {
+            killers[1] = killers[0];
+            killers[0] = Some(m);
+        }
 
+        if let Some((from, to)) = self.previous_move_squares() {
+            context.countermoves[from][to] = Some(m);
+        }
 
-            if m.m.score >= beta {
-                // println!("Pruning");
-                return beta;
+        let color = self.current_color() as usize;
+        let bonus = depth * depth;
+        let mut overflow = false;
+        for (quiet, change) in std::iter::once((&m, bonus)).chain(quiets_searched.iter().map(|q| (q, -bonus))) {
+            if let Some((piece, to)) = self.move_endpoints(quiet) {
+                let entry = &mut context.history[color][square_index(piece.position)][square_index(to)];
+                *entry += change;
+                overflow |= entry.abs() > HISTORY_LIMIT;
             }
+        }
 
-            if m.m.score > alpha {
-                alpha = m.m.score;
+        // keep history within the range between the capture priorities
+        if overflow {
+            for value in context.history.iter_mut().flatten().flatten() {
+                *value /= 2;
             }
         }
+    }
 
-        return alpha;
+    // squares of the move that led to this position, None after a null move
+    fn previous_move_squares(&self) -> Option<(usize, usize)> {
+        let m = self.last_move()?;
+        // the king has already castled, move_endpoints would look for it on its home square
+        let castle = |color: Color, to_x| {
+            let rank = color.home_rank();
+            Some((square_index(Position::new(4, rank)), square_index(Position::new(to_x, rank))))
+        };
+        match m.action {
+            Action::CastleKingSide { color } => castle(color, 6),
+            Action::CastleQueenSide { color } => castle(color, 2),
+            _ => self.move_endpoints(&m).map(|(piece, to)| (square_index(piece.position), square_index(to))),
+        }
     }
 
     pub fn white_pieces(&self) -> [Option<Piece>; 16] {
@@ -574,11 +1563,11 @@ impl Board {
     }
 
     pub fn current_color(&self) -> Color {
-        if self.move_stack.len() % 2 == 0 {
-            Color::White
-        } else {
-            Color::Black
-        }
+        self.side_to_move
+    }
+
+    pub fn fullmove_number(&self) -> u32 {
+        self.fullmove_number
     }
 
     pub fn piece_at(&self, position: &Position) -> &Option<Piece> {
@@ -621,110 +1610,370 @@ impl Board {
         // white_value += white_activity;
         // black_value += black_activity;
 
-        let perspective = if self.current_color() == Color::White {
-            1
-        } else {
-            -1
-        };
-        (white_value - black_value) * perspective
+        let perspective = if self.side_to_move == Color::White {
+            1
+        } else {
+            -1
+        };
+        (white_value - black_value) * perspective
+    }
+
+    /*
+    pub fn compute_attacked_cells(&mut self) {
+        for y in 0..8 {
+            for x in 0..8 {
+                self.cell_mut_at(Position::new(x, y)).attacking_white_pieces = 0;
+                self.cell_mut_at(Position::new(x, y)).attacking_black_pieces = 0;
+            }
+        }
+
+        let white_moves = self.collect_all_moves(Color::White, false, true);
+        for m in &white_moves {
+            match &m.m.action {
+                Action::Move { to, .. } => {
+                    self.cell_mut_at(to.position).attacking_white_pieces += 1;
+                }
+                Action::Capture { piece, target } => {
+                    self.cell_mut_at(target.position).attacking_white_pieces += 1;
+                }
+                Action::Promote { .. } => {}
+                Action::NoAction => {}
+            }
+        }
+
+        let black_moves = self.collect_all_moves(Color::Black, false, true);
+        for m in &black_moves {
+            match &m.m.action {
+                Action::Move { to, .. } => {
+                    self.cell_mut_at(to.position).attacking_black_pieces += 1;
+                }
+                Action::Capture { piece, target } => {
+                    self.cell_mut_at(target.position).attacking_black_pieces += 1;
+                }
+                Action::Promote { .. } => {}
+                Action::NoAction => {}
+            }
+        }
+    }
+    */
+    pub fn collect_piece_moves(&self, piece: &Piece) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(14);
+        self.append_piece_moves(piece, &mut moves, false, false);
+        return moves;
+    }
+
+    pub fn collect_all_moves(
+        &self,
+        color: Color,
+        only_captures: bool,
+        include_control: bool,
+    ) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(30);
+        let pieces = match color {
+            Color::White => &self.white_pieces[0..self.used_white_pieces],
+            Color::Black => &self.black_pieces[0..self.used_black_pieces],
+        };
+
+        for piece in pieces {
+            if let Some(piece) = piece {
+                self.append_piece_moves(&piece, &mut moves, only_captures, include_control);
+            }
+        }
+
+        moves
+    }
+
+    pub fn collect_legal_moves(&self, only_captures: bool) -> Vec<Move> {
+        let color = self.current_color();
+        let moves = self.collect_all_moves(color, only_captures, false);
+        self.filter_legal_moves(color, moves)
+    }
+
+    pub fn collect_legal_piece_moves(&self, piece: &Piece) -> Vec<Move> {
+        let moves = self.collect_piece_moves(piece);
+        self.filter_legal_moves(piece.color, moves)
+    }
+
+    fn filter_legal_moves(&self, color: Color, moves: Vec<Move>) -> Vec<Move> {
+        // positions without a king (pawn games) have no notion of check
+        let king = match self.king_position(color) {
+            Some(king) => king,
+            None => return moves,
+        };
+
+        let checkers = self.attackers(king, color.opposite(), &[], None);
+        let pins = self.pinned_pieces(color, king);
+
+        moves
+            .into_iter()
+            .filter(|m| self.is_legal_move(m, color, king, &checkers, &pins))
+            .collect()
+    }
+
+    fn is_legal_move(
+        &self,
+        m: &Move,
+        color: Color,
+        king: Position,
+        checkers: &[Piece],
+        pins: &[(Position, (i8, i8))],
+    ) -> bool {
+        let (piece, to) = match self.move_endpoints(m) {
+            Some(endpoints) => endpoints,
+            None => return false,
+        };
+
+        if let Action::EnPassant { piece, to, target } = m.action {
+            // two pawns leave the same rank at once, so just look at the resulting position
+            return self.attackers(king, color.opposite(), &[piece.position, target.position], Some(to)).is_empty();
+        }
+
+        if piece.t == Type::King {
+            // the king itself must not block the ray of a slider checking it
+            return self.attackers(to, color.opposite(), &[piece.position], None).is_empty();
+        }
+
+        if checkers.len() > 1 {
+            return false;
+        }
+
+        if let Some(checker) = checkers.first() {
+            let blocks = match checker.t {
+                Type::Rook | Type::Bishop | Type::Queen => is_between(king, checker.position, to),
+                _ => false,
+            };
+            if to != checker.position && !blocks {
+                return false;
+            }
+        }
+
+        if let Some((_, (dx, dy))) = pins.iter().find(|(position, _)| *position == piece.position) {
+            // a pinned piece can only slide along the pin ray
+            let x = to.x - king.x;
+            let y = to.y - king.y;
+            if x * dy != y * dx {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    // moving piece and its destination, for castling this is the king
+    pub fn move_endpoints(&self, m: &Move) -> Option<(Piece, Position)> {
+        match m.action {
+            Action::Move { from, to } => Some((from, to.position)),
+            Action::Capture { piece, target } => Some((piece, target.position)),
+            Action::Promote { old_piece, new_piece } | Action::CapturePromote { old_piece, new_piece, .. } => {
+                Some((old_piece, new_piece.position))
+            }
+            Action::EnPassant { piece, to, .. } => Some((piece, to)),
+            Action::CastleKingSide { color } => {
+                let rank = color.home_rank();
+                self.piece_at(&Position::new(4, rank)).map(|king| (king, Position::new(6, rank)))
+            }
+            Action::CastleQueenSide { color } => {
+                let rank = color.home_rank();
+                self.piece_at(&Position::new(4, rank)).map(|king| (king, Position::new(2, rank)))
+            }
+            Action::NoAction => None,
+        }
+    }
+
+    pub fn king_position(&self, color: Color) -> Option<Position> {
+        let pieces = match color {
+            Color::White => &self.white_pieces[0..self.used_white_pieces],
+            Color::Black => &self.black_pieces[0..self.used_black_pieces],
+        };
+
+        pieces
+            .iter()
+            .flatten()
+            .find(|piece| piece.t == Type::King)
+            .map(|piece| piece.position)
+    }
+
+    pub fn is_in_check(&self, color: Color) -> bool {
+        match self.king_position(color) {
+            Some(king) => self.is_square_attacked(king, color.opposite()),
+            None => false,
+        }
+    }
+
+    pub fn is_square_attacked(&self, position: Position, by: Color) -> bool {
+        !self.attackers(position, by, &[], None).is_empty()
     }
 
-    // black or white: 2 values -> 1 bit
-    // nothing, pawn, rook, knight, bishop, queen, king: 7 values -> 3 bits
-    // 4 bits per cell, 64*4 = 256 bits total per board position
-    pub fn position_hash(&self) -> (u64, u64, u64, u64) {
-        let hash = |rows: &[[Cell; 8]]| {
-            let mut value: u64 = 0;
-            for row in rows {
-                for cell in row {
-                    value <<= 4;
-                    value *= 2 << 4;
-                    if let Some(piece) = &cell.piece {
-                        value += if piece.color == Color::White { 1 } else { 0 };
-                        value += piece.t as u64;
+    // pieces of color `by` attacking `position`, as if the `vacated` cells were empty
+    // and `filled` held a blocking piece
+    fn attackers(
+        &self,
+        position: Position,
+        by: Color,
+        vacated: &[Position],
+        filled: Option<Position>,
+    ) -> Vec<Piece> {
+        let occupant = |p: Position| -> Option<Piece> {
+            if vacated.contains(&p) || filled == Some(p) {
+                None
+            } else {
+                *self.piece_at(&p)
+            }
+        };
+        let is_blocked = |p: Position| filled == Some(p) || occupant(p).is_some();
+        let is_attacker = |piece: &Piece, types: &[Type]| piece.color == by && types.contains(&piece.t);
+
+        let mut attackers = Vec::new();
+
+        for (directions, types) in [
+            (ROOK_DIRECTIONS, [Type::Rook, Type::Queen]),
+            (BISHOP_DIRECTIONS, [Type::Bishop, Type::Queen]),
+        ] {
+            for (dx, dy) in directions {
+                let mut current = position.offset(dx, dy);
+                while let Some(p) = current {
+                    if is_blocked(p) {
+                        if let Some(piece) = occupant(p) {
+                            if is_attacker(&piece, &types) {
+                                attackers.push(piece);
+                            }
+                        }
+                        break;
                     }
+                    current = p.offset(dx, dy);
                 }
             }
-            value
-        };
-
-        let v1 = hash(&self.cells[0..2]);
-        let v2 = hash(&self.cells[0..4]);
-        let v3 = hash(&self.cells[0..6]);
-        let v4 = hash(&self.cells[0..8]);
-
-        (v1, v2, v3, v4)
-    }
+        }
 
-    /*
-    pub fn compute_attacked_cells(&mut self) {
-        for y in 0..8 {
-            for x in 0..8 {
-                self.cell_mut_at(Position::new(x, y)).attacking_white_pieces = 0;
-                self.cell_mut_at(Position::new(x, y)).attacking_black_pieces = 0;
+        for (dx, dy) in KNIGHT_OFFSETS {
+            if let Some(piece) = position.offset(dx, dy).and_then(occupant) {
+                if is_attacker(&piece, &[Type::Knight]) {
+                    attackers.push(piece);
+                }
             }
         }
 
-        let white_moves = self.collect_all_moves(Color::White, false, true);
-        for m in &white_moves {
-            match &m.m.action {
-                Action::Move { to, .. } => {
-                    self.cell_mut_at(to.position).attacking_white_pieces += 1;
-                }
-                Action::Capture { piece, target } => {
-                    self.cell_mut_at(target.position).attacking_white_pieces += 1;
+        for (dx, dy) in ROOK_DIRECTIONS.iter().chain(BISHOP_DIRECTIONS.iter()) {
+            if let Some(piece) = position.offset(*dx, *dy).and_then(occupant) {
+                if is_attacker(&piece, &[Type::King]) {
+                    attackers.push(piece);
                 }
-                Action::Promote { .. } => {}
-                Action::NoAction => {}
             }
         }
 
-        let black_moves = self.collect_all_moves(Color::Black, false, true);
-        for m in &black_moves {
-            match &m.m.action {
-                Action::Move { to, .. } => {
-                    self.cell_mut_at(to.position).attacking_black_pieces += 1;
-                }
-                Action::Capture { piece, target } => {
-                    self.cell_mut_at(target.position).attacking_black_pieces += 1;
+        // pawns attack diagonally forward, so look backwards from the target
+        let pawn_dy = if by == Color::White { -1 } else { 1 };
+        for dx in [-1, 1] {
+            if let Some(piece) = position.offset(dx, pawn_dy).and_then(occupant) {
+                if is_attacker(&piece, &[Type::Pawn]) {
+                    attackers.push(piece);
                 }
-                Action::Promote { .. } => {}
-                Action::NoAction => {}
             }
         }
-    }
-    */
-    pub fn collect_piece_moves(&self, piece: &Piece) -> Vec<MoveNode> {
-        let mut moves = Vec::with_capacity(14);
-        self.append_piece_moves(piece, &mut moves, false, false);
-        return moves;
+
+        attackers
     }
 
-    pub fn collect_all_moves(
-        &self,
-        color: Color,
-        only_captures: bool,
-        include_control: bool,
-    ) -> Vec<MoveNode> {
-        let mut moves = Vec::with_capacity(30);
-        let pieces = match color {
-            Color::White => &self.white_pieces[0..self.used_white_pieces],
-            Color::Black => &self.black_pieces[0..self.used_black_pieces],
+    // static exchange evaluation: material won by the side playing `m` when both sides
+    // then recapture on the target cell with their least valuable piece, for as long as
+    // it pays off. Pieces behind an attacker join in once it has left, pins are ignored.
+    pub fn static_exchange(&self, m: &Move) -> i16 {
+        let (piece, to) = match m.action {
+            Action::CastleKingSide { .. } | Action::CastleQueenSide { .. } | Action::NoAction => return 0,
+            _ => self.move_endpoints(m).unwrap(),
         };
 
-        for piece in pieces {
-            if let Some(piece) = piece {
-                self.append_piece_moves(&piece, &mut moves, only_captures, include_control);
+        let mut vacated = vec![piece.position];
+        if let Action::EnPassant { target, .. } = m.action {
+            vacated.push(target.position);
+        }
+
+        // gains[i] is the balance for the side making the i-th capture if the exchange stopped there
+        let mut gains = vec![m.captured().map_or(0, |target| target.base_value())];
+        let mut on_target = match m.action {
+            Action::Promote { new_piece, .. } | Action::CapturePromote { new_piece, .. } => {
+                gains[0] += new_piece.base_value() - piece.base_value();
+                new_piece.base_value()
             }
+            _ => piece.base_value(),
+        };
+
+        let mut color = piece.color.opposite();
+        loop {
+            let attacker = match self.attackers(to, color, &vacated, None).into_iter().min_by_key(|a| a.base_value()) {
+                Some(attacker) => attacker,
+                None => break,
+            };
+            vacated.push(attacker.position);
+            // the king can't capture a defended piece
+            if attacker.t == Type::King && !self.attackers(to, color.opposite(), &vacated, None).is_empty() {
+                break;
+            }
+
+            gains.push(on_target - gains.last().unwrap());
+            on_target = attacker.base_value();
+            color = color.opposite();
         }
 
-        moves
+        // each side can stop recapturing when it would lose material
+        while gains.len() > 1 {
+            let last = gains.pop().unwrap();
+            let previous = gains.last_mut().unwrap();
+            *previous = -(-*previous).max(last);
+        }
+        gains[0]
+    }
+
+    // taking a piece worth at least as much as the capturing one can't lose material,
+    // the exchange only needs to be resolved for the other captures
+    fn is_losing_capture(&self, m: &Move) -> bool {
+        match (self.move_endpoints(m), m.captured()) {
+            (Some((piece, _)), Some(target)) if target.base_value() >= piece.base_value() => false,
+            // legal king captures are never defended
+            (Some((piece, _)), Some(_)) if piece.t == Type::King => false,
+            _ => self.static_exchange(m) < 0,
+        }
+    }
+
+    // pieces of `color` pinned against their king, with the direction of the pin ray
+    fn pinned_pieces(&self, color: Color, king: Position) -> Vec<(Position, (i8, i8))> {
+        let mut pins = Vec::new();
+
+        for (directions, types) in [
+            (ROOK_DIRECTIONS, [Type::Rook, Type::Queen]),
+            (BISHOP_DIRECTIONS, [Type::Bishop, Type::Queen]),
+        ] {
+            for (dx, dy) in directions {
+                let mut candidate: Option<Position> = None;
+                let mut current = king.offset(dx, dy);
+                while let Some(p) = current {
+                    if let Some(piece) = self.piece_at(&p) {
+                        if piece.color == color {
+                            if candidate.is_some() {
+                                break;
+                            }
+                            candidate = Some(p);
+                        } else {
+                            if let Some(pinned) = candidate {
+                                if types.contains(&piece.t) {
+                                    pins.push((pinned, (dx, dy)));
+                                }
+                            }
+                            break;
+                        }
+                    }
+                    current = p.offset(dx, dy);
+                }
+            }
+        }
+
+        pins
     }
 
     pub fn append_piece_moves(
         &self,
         piece: &Piece,
-        moves: &mut Vec<MoveNode>,
+        moves: &mut Vec<Move>,
         only_captures: bool,
         include_control: bool,
     ) {
@@ -744,7 +1993,7 @@ impl Board {
     pub fn append_knight_moves(
         &self,
         piece: &Piece,
-        moves: &mut Vec<MoveNode>,
+        moves: &mut Vec<Move>,
         only_captures: bool,
     ) {
         let dx = [1, -1, -2, -2, -1, 1, 2, 2];
@@ -762,22 +2011,22 @@ impl Board {
 
             if let Some(target) = self.piece_at(&position) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, position).into());
+                moves.push(Move::move_piece(*piece, position));
             }
         }
     }
 
-    pub fn append_king_moves(&self, piece: &Piece, moves: &mut Vec<MoveNode>, only_captures: bool) {
+    pub fn append_king_moves(&self, piece: &Piece, moves: &mut Vec<Move>, only_captures: bool) {
         let mut try_position = |position| {
             if let Some(target) = self.piece_at(&position) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, position).into());
+                moves.push(Move::move_piece(*piece, position));
             }
         };
         if let Some(position) = piece.position.left(1) {
@@ -812,95 +2061,75 @@ impl Board {
             try_position(position);
         }
 
-        if piece.color == Color::White && self.white_king_move_count == 0 {
-            if self.white_king_rook_move_count == 0 {
-                if let Some(maybe_rook) = self.piece_at(&Position::new(7, 0)) {
-                    if self.piece_at(&Position::new(5, 0)).is_none() &&
-                        self.piece_at(&Position::new(6, 0)).is_none() &&
-                        maybe_rook.color == Color::White {
-                        moves.push(Move { score: 0, action: Action::CastleKingSide }.into());
-                    }
-                }
-            }
-
-            if self.white_queen_rook_move_count == 0 {
-                if let Some(maybe_rook) = self.piece_at(&Position::new(0, 0)) {
-                    if self.piece_at(&Position::new(1, 0)).is_none() &&
-                        self.piece_at(&Position::new(2, 0)).is_none() &&
-                        self.piece_at(&Position::new(3, 0)).is_none() &&
-                        maybe_rook.color == Color::White {
-                        moves.push(Move { score: 0, action: Action::CastleQueenSide }.into());
-                    }
-                }
-            }
+        let rank = if piece.color == Color::White { 0 } else { 7 };
+        let enemy = piece.color.opposite();
+        if piece.position != Position::new(4, rank) || self.is_square_attacked(piece.position, enemy) {
+            return;
         }
 
-        if piece.color == Color::Black && self.black_king_move_count == 0 {
-            if self.black_king_rook_move_count == 0 {
-                if let Some(maybe_rook) = self.piece_at(&Position::new(7, 7)) {
-                    if self.piece_at(&Position::new(5, 7)).is_none() &&
-                        self.piece_at(&Position::new(6, 7)).is_none() &&
-                        maybe_rook.color == Color::Black {
-                        moves.push(Move { score: 0, action: Action::CastleKingSide }.into());
-                    }
-                }
-            }
+        // the rook must be home, the cells in between empty, and the king may not pass
+        // through or land on an attacked cell
+        let can_castle = |rook_x: i8, empty: &[i8], safe: &[i8]| {
+            let rook_home = match self.piece_at(&Position::new(rook_x, rank)) {
+                Some(rook) => rook.t == Type::Rook && rook.color == piece.color,
+                None => false,
+            };
+            rook_home
+                && empty.iter().all(|x| self.piece_at(&Position::new(*x, rank)).is_none())
+                && safe.iter().all(|x| !self.is_square_attacked(Position::new(*x, rank), enemy))
+        };
 
-            if self.black_queen_rook_move_count == 0 {
-                if let Some(maybe_rook) = self.piece_at(&Position::new(0, 7)) {
-                    if self.piece_at(&Position::new(1, 7)).is_none() &&
-                        self.piece_at(&Position::new(2, 7)).is_none() &&
-                        self.piece_at(&Position::new(3, 7)).is_none() &&
-                        maybe_rook.color == Color::Black {
-                        moves.push(Move { score: 0, action: Action::CastleQueenSide }.into());
-                    }
-                }
-            }
+        if self.castling_rights.king_side(piece.color) && can_castle(7, &[5, 6], &[5, 6]) {
+            moves.push(Move { score: 0, action: Action::CastleKingSide { color: piece.color } });
+        }
+
+        if self.castling_rights.queen_side(piece.color) && can_castle(0, &[1, 2, 3], &[2, 3]) {
+            moves.push(Move { score: 0, action: Action::CastleQueenSide { color: piece.color } });
         }
     }
 
-    pub fn append_rook_moves(&self, piece: &Piece, moves: &mut Vec<MoveNode>, only_captures: bool) {
+    pub fn append_rook_moves(&self, piece: &Piece, moves: &mut Vec<Move>, only_captures: bool) {
         for x in piece.position.x + 1..=7 {
             if let Some(target) = self.piece_at(&Position::new(x, piece.position.y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(x, piece.position.y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(x, piece.position.y)));
             }
         }
 
         for x in (0..piece.position.x).rev() {
             if let Some(target) = self.piece_at(&Position::new(x, piece.position.y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(x, piece.position.y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(x, piece.position.y)));
             }
         }
 
         for y in piece.position.y + 1..=7 {
             if let Some(target) = self.piece_at(&Position::new(piece.position.x, y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(piece.position.x, y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(piece.position.x, y)));
             }
         }
 
         for y in (0..piece.position.y).rev() {
             if let Some(target) = self.piece_at(&Position::new(piece.position.x, y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(piece.position.x, y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(piece.position.x, y)));
             }
         }
     }
@@ -908,7 +2137,7 @@ impl Board {
     pub fn append_bishop_moves(
         &self,
         piece: &Piece,
-        moves: &mut Vec<MoveNode>,
+        moves: &mut Vec<Move>,
         only_captures: bool,
     ) {
         // up - right
@@ -917,11 +2146,11 @@ impl Board {
             let y = piece.position.y + n;
             if let Some(target) = self.piece_at(&Position::new(x, y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(x, y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(x, y)));
             }
         }
 
@@ -931,11 +2160,11 @@ impl Board {
             let y = piece.position.y + n;
             if let Some(target) = self.piece_at(&Position::new(x, y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(x, y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(x, y)));
             }
         }
 
@@ -945,11 +2174,11 @@ impl Board {
             let y = piece.position.y - n;
             if let Some(target) = self.piece_at(&Position::new(x, y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(x, y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(x, y)));
             }
         }
 
@@ -959,11 +2188,11 @@ impl Board {
             let y = piece.position.y - n;
             if let Some(target) = self.piece_at(&Position::new(x, y)) {
                 if target.color != piece.color {
-                    moves.push(Move::capture_piece(*piece, *target).into());
+                    moves.push(Move::capture_piece(*piece, *target));
                 }
                 break;
             } else if !only_captures {
-                moves.push(Move::move_piece(*piece, Position::new(x, y)).into());
+                moves.push(Move::move_piece(*piece, Position::new(x, y)));
             }
         }
     }
@@ -971,7 +2200,7 @@ impl Board {
     pub fn append_pawn_moves(
         &self,
         piece: &Piece,
-        moves: &mut Vec<MoveNode>,
+        moves: &mut Vec<Move>,
         only_captures: bool,
         include_control: bool,
     ) {
@@ -980,36 +2209,38 @@ impl Board {
                 if let Some(position) = piece.position.up_left(1) {
                     if let Some(target) = self.piece_at(&position) {
                         if target.color != piece.color {
-                            moves.push(Move::capture_piece(*piece, *target).into());
+                            self.append_pawn_capture(piece, target, moves);
                         }
                     } else if include_control {
-                        moves.push(Move::move_piece(*piece, position).into());
+                        moves.push(Move::move_piece(*piece, position));
                     }
                 }
 
                 if let Some(position) = piece.position.up_right(1) {
                     if let Some(target) = self.piece_at(&position) {
                         if target.color != piece.color {
-                            moves.push(Move::capture_piece(*piece, *target).into());
+                            self.append_pawn_capture(piece, target, moves);
                         }
                     } else if include_control {
-                        moves.push(Move::move_piece(*piece, position).into());
+                        moves.push(Move::move_piece(*piece, position));
                     }
                 }
 
+                self.append_en_passant_move(piece, moves);
+
                 // promotes
                 if piece.position.y == 6 {
                     if let None = self.piece_at(&piece.position.up(1).unwrap()) {
-                        moves.push(
-                            Move::promote(*piece, piece.position.up(1).unwrap(), Type::Queen).into(),
-                        );
+                        for t in PROMOTION_TYPES {
+                            moves.push(Move::promote(*piece, piece.position.up(1).unwrap(), t));
+                        }
                     }
                 }
 
-                if !only_captures && !include_control {
+                if !only_captures && !include_control && piece.position.y != 6 {
                     if let Some(position) = piece.position.up(1) {
                         if let None = self.piece_at(&position) {
-                            moves.push(Move::move_piece(*piece, position).into());
+                            moves.push(Move::move_piece(*piece, position));
                         }
                     }
 
@@ -1017,7 +2248,7 @@ impl Board {
                         if let None = self.piece_at(&piece.position.up(1).unwrap()) {
                             let target = piece.position.up(2).unwrap();
                             if let None = self.piece_at(&target) {
-                                moves.push(Move::move_piece(*piece, target).into());
+                                moves.push(Move::move_piece(*piece, target));
                             }
                         }
                     }
@@ -1028,37 +2259,38 @@ impl Board {
                 if let Some(position) = piece.position.down_left(1) {
                     if let Some(target) = self.piece_at(&position) {
                         if target.color != piece.color {
-                            moves.push(Move::capture_piece(*piece, *target).into());
+                            self.append_pawn_capture(piece, target, moves);
                         }
                     } else if include_control {
-                        moves.push(Move::move_piece(*piece, position).into());
+                        moves.push(Move::move_piece(*piece, position));
                     }
                 }
 
                 if let Some(position) = piece.position.down_right(1) {
                     if let Some(target) = self.piece_at(&position) {
                         if target.color != piece.color {
-                            moves.push(Move::capture_piece(*piece, *target).into());
+                            self.append_pawn_capture(piece, target, moves);
                         }
                     } else if include_control {
-                        moves.push(Move::move_piece(*piece, position).into());
+                        moves.push(Move::move_piece(*piece, position));
                     }
                 }
 
+                self.append_en_passant_move(piece, moves);
+
                 // promotes
                 if piece.position.y == 1 {
                     if let None = self.piece_at(&piece.position.down(1).unwrap()) {
-                        moves.push(
-                            Move::promote(*piece, piece.position.down(1).unwrap(), Type::Queen)
-                                .into(),
-                        );
+                        for t in PROMOTION_TYPES {
+                            moves.push(Move::promote(*piece, piece.position.down(1).unwrap(), t));
+                        }
                     }
                 }
 
-                if !only_captures && !include_control {
+                if !only_captures && !include_control && piece.position.y != 1 {
                     if let Some(position) = piece.position.down(1) {
                         if let None = self.piece_at(&position) {
-                            moves.push(Move::move_piece(*piece, position).into());
+                            moves.push(Move::move_piece(*piece, position));
                         }
                     }
 
@@ -1066,7 +2298,7 @@ impl Board {
                         if let None = self.piece_at(&piece.position.down(1).unwrap()) {
                             let target = piece.position.down(2).unwrap();
                             if let None = self.piece_at(&target) {
-                                moves.push(Move::move_piece(*piece, target).into());
+                                moves.push(Move::move_piece(*piece, target));
                             }
                         }
                     }
@@ -1075,18 +2307,33 @@ impl Board {
         }
     }
 
-    pub fn sort_moves(&self, moves: &mut Vec<MoveNode>) {
-        moves.sort_by(|lh, rh| {
-            let v1 = lh.m.value() + lh.m.score;
-            let v2 = rh.m.value() + rh.m.score;
-            if v1 > v2 {
-                Ordering::Less
-            } else if v2 > v1 {
-                Ordering::Greater
-            } else {
-                Ordering::Equal
+    fn append_pawn_capture(&self, piece: &Piece, target: &Piece, moves: &mut Vec<Move>) {
+        let last_rank = if piece.color == Color::White { 7 } else { 0 };
+        if target.position.y == last_rank {
+            for t in PROMOTION_TYPES {
+                moves.push(Move::promote_capture(*piece, *target, t));
             }
-        });
+        } else {
+            moves.push(Move::capture_piece(*piece, *target));
+        }
+    }
+
+    fn append_en_passant_move(&self, piece: &Piece, moves: &mut Vec<Move>) {
+        let to = match self.en_passant {
+            Some(to) => to,
+            None => return,
+        };
+
+        let dy = if piece.color == Color::White { 1 } else { -1 };
+        if to.y != piece.position.y + dy || (to.x - piece.position.x).abs() != 1 {
+            return;
+        }
+
+        if let Some(target) = self.piece_at(&Position::new(to.x, piece.position.y)) {
+            if target.t == Type::Pawn && target.color != piece.color {
+                moves.push(Move::en_passant(*piece, to, *target));
+            }
+        }
     }
 
     pub fn cell_mut_at(&mut self, position: Position) -> &mut Cell {
@@ -1168,6 +2415,7 @@ impl Board {
 
         self.cell_mut_at(to.position).piece = Some(to);
         self.cell_mut_at(from.position).piece = None;
+        self.key ^= zobrist::piece(&from) ^ zobrist::piece(&to);
 
         debug_assert_eq!(from.index, to.index);
 
@@ -1183,6 +2431,7 @@ impl Board {
 
     fn remove_piece(&mut self, piece: Piece) {
         self.cell_mut_at(piece.position).piece = None;
+        self.key ^= zobrist::piece(&piece);
         match piece.color {
             Color::White => {
                 self.white_pieces[piece.index as usize] = None;
@@ -1197,6 +2446,7 @@ impl Board {
 
     fn add_piece(&mut self, piece: Piece) {
         self.cell_mut_at(piece.position).piece = Some(piece);
+        self.key ^= zobrist::piece(&piece);
         match piece.color {
             Color::White => {
                 self.white_pieces[piece.index as usize] = Some(piece);
@@ -1210,19 +2460,36 @@ impl Board {
     }
 
     fn make_move(&mut self, m: Move) {
+        self.state_stack.push(BoardState {
+            en_passant: self.en_passant,
+            halfmove_clock: self.halfmove_clock,
+            castling_rights: self.castling_rights,
+            key: self.key,
+        });
+        self.key ^= zobrist::castling(&self.castling_rights) ^ zobrist::en_passant(self.en_passant);
+        self.en_passant = None;
+
+        let color = self.side_to_move;
+        if let Some((piece, to)) = self.move_endpoints(&m) {
+            self.castling_rights.revoke(piece.position);
+            self.castling_rights.revoke(to);
+        }
+
+        self.halfmove_clock = match m.action {
+            Action::Move { from, .. } if from.t != Type::Pawn => self.halfmove_clock + 1,
+            Action::CastleKingSide { .. } | Action::CastleQueenSide { .. } => self.halfmove_clock + 1,
+            // also after a null move, so repetitions aren't detected across it
+            _ => 0,
+        };
+
         match m.action {
-            Action::NoAction => {
-                unreachable!()
-            }
+            // a null move only passes the turn
+            Action::NoAction => {}
             Action::Move { from, to } => {
                 // self.remove_piece_attack(&from);
                 self.move_piece(from, to);
-                if from.t == Type::King {
-                    if from.color == Color::White {
-                        self.white_king_move_count += 1;
-                    } else {
-                        self.black_king_move_count += 1;
-                    }
+                if from.t == Type::Pawn && (to.position.y - from.position.y).abs() == 2 {
+                    self.en_passant = Some(Position::new(from.position.x, (from.position.y + to.position.y) / 2));
                 }
                 // self.add_piece_attack(&to);
             }
@@ -1231,13 +2498,6 @@ impl Board {
                 // self.remove_piece_attack(&piece);
                 self.remove_piece(target);
                 self.move_piece(piece, piece.moved(target.position));
-                if piece.t == Type::King {
-                    if piece.color == Color::White {
-                        self.white_king_move_count += 1;
-                    } else {
-                        self.black_king_move_count += 1;
-                    }
-                }
                 // self.add_piece_attack(&piece.moved(target.position));
             }
             Action::Promote {
@@ -1248,77 +2508,72 @@ impl Board {
                 self.move_piece(old_piece, new_piece);
                 // self.add_piece_attack(&new_piece);
             }
-            Action::CastleKingSide => {
-                if self.current_color() == Color::White {
+            Action::CapturePromote { old_piece, new_piece, target } => {
+                self.remove_piece(target);
+                self.move_piece(old_piece, new_piece);
+            }
+            Action::EnPassant { piece, to, target } => {
+                self.remove_piece(target);
+                self.move_piece(piece, piece.moved(to));
+            }
+            Action::CastleKingSide { .. } => {
+                if color == Color::White {
                     let king = self.piece_at(&Position::new(4, 0)).unwrap();
                     let rook = self.piece_at(&Position::new(7, 0)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(6, 0)));
                     self.move_piece(rook, rook.moved(Position::new(5, 0)));
-
-                    self.white_king_move_count += 1;
-                    self.white_king_rook_move_count += 1;
                 } else {
                     let king = self.piece_at(&Position::new(4, 7)).unwrap();
                     let rook = self.piece_at(&Position::new(7, 7)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(6, 7)));
                     self.move_piece(rook, rook.moved(Position::new(5, 7)));
-
-                    self.black_king_move_count += 1;
-                    self.black_king_rook_move_count += 1;
                 }
             }
-            Action::CastleQueenSide => {
-                if self.current_color() == Color::White {
+            Action::CastleQueenSide { .. } => {
+                if color == Color::White {
                     let king = self.piece_at(&Position::new(4, 0)).unwrap();
                     let rook = self.piece_at(&Position::new(0, 0)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(2, 0)));
                     self.move_piece(rook, rook.moved(Position::new(3, 0)));
-
-                    self.white_king_move_count += 1;
-                    self.white_queen_rook_move_count += 1;
                 } else {
                     let king = self.piece_at(&Position::new(4, 7)).unwrap();
                     let rook = self.piece_at(&Position::new(0, 7)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(2, 7)));
                     self.move_piece(rook, rook.moved(Position::new(3, 7)));
-
-                    self.black_king_move_count += 1;
-                    self.black_queen_rook_move_count += 1;
                 }
             }
         }
+
+        if color == Color::Black {
+            self.fullmove_number += 1;
+        }
+        self.side_to_move = color.opposite();
+        self.key ^= zobrist::castling(&self.castling_rights) ^ zobrist::en_passant(self.en_passant) ^ zobrist::side();
+        debug_assert_eq!(self.key, self.compute_key());
     }
 
     fn unmake_move(&mut self, m: Move) {
+        let color = self.side_to_move.opposite();
+        self.side_to_move = color;
+        if color == Color::Black {
+            self.fullmove_number -= 1;
+        }
+
         match m.action {
-            Action::NoAction => unreachable!(),
+            Action::NoAction => {}
             Action::Move { from, to } => {
                 // self.remove_piece_attack(&to);
                 self.move_piece(to, from);
-                if from.t == Type::King {
-                    if from.color == Color::White {
-                        self.white_king_move_count -= 1;
-                    } else {
-                        self.black_king_move_count -= 1;
-                    }
-                }
                 // self.add_piece_attack(&from);
             }
             Action::Capture { piece, target } => {
                 // self.remove_piece_attack(&piece.moved(target.position));
                 self.move_piece(piece.moved(target.position), piece);
                 self.add_piece(target);
-                if piece.t == Type::King {
-                    if piece.color == Color::White {
-                        self.white_king_move_count -= 1;
-                    } else {
-                        self.black_king_move_count -= 1;
-                    }
-                }
                 // self.add_piece_attack(&target);
                 // self.add_piece_attack(&piece);
             }
@@ -1330,65 +2585,56 @@ impl Board {
                 self.move_piece(new_piece, old_piece);
                 // self.add_piece_attack(&old_piece);
             }
-            Action::CastleKingSide => {
-                if self.current_color() == Color::White {
+            Action::CapturePromote { old_piece, new_piece, target } => {
+                self.move_piece(new_piece, old_piece);
+                self.add_piece(target);
+            }
+            Action::EnPassant { piece, to, target } => {
+                self.move_piece(piece.moved(to), piece);
+                self.add_piece(target);
+            }
+            Action::CastleKingSide { .. } => {
+                if color == Color::White {
                     let king = self.piece_at(&Position::new(6, 0)).unwrap();
                     let rook = self.piece_at(&Position::new(5, 0)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(4, 0)));
                     self.move_piece(rook, rook.moved(Position::new(7, 0)));
-
-                    self.white_king_move_count -= 1;
-                    self.white_king_rook_move_count -= 1;
                 } else {
                     let king = self.piece_at(&Position::new(6, 7)).unwrap();
                     let rook = self.piece_at(&Position::new(5, 7)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(4, 7)));
                     self.move_piece(rook, rook.moved(Position::new(7, 7)));
-
-                    self.black_king_move_count -= 1;
-                    self.black_king_rook_move_count -= 1;
                 }
             }
-            Action::CastleQueenSide => {
-                if self.current_color() == Color::White {
+            Action::CastleQueenSide { .. } => {
+                if color == Color::White {
                     let king = self.piece_at(&Position::new(2, 0)).unwrap();
                     let rook = self.piece_at(&Position::new(3, 0)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(4, 0)));
                     self.move_piece(rook, rook.moved(Position::new(0, 0)));
-
-                    self.white_king_move_count -= 1;
-                    self.white_queen_rook_move_count -= 1;
                 } else {
                     let king = self.piece_at(&Position::new(2, 7)).unwrap();
                     let rook = self.piece_at(&Position::new(3, 7)).unwrap();
 
                     self.move_piece(king, king.moved(Position::new(4, 7)));
                     self.move_piece(rook, rook.moved(Position::new(0, 7)));
-
-                    self.black_king_move_count -= 1;
-                    self.black_queen_rook_move_count -= 1;
                 }
             }
         }
-    }
-
-    pub fn make_move_root(&mut self, m: Move) {
-        let children = self.root_node.take().unwrap().children;
-        for child in children {
-            if child.m == m {
-                self.root_node = Some(child);
-                break;
-            }
-        }
 
-        // if no child was found, just reset the root node
-        self.root_node = Some(Move { score: 0, action: Action::NoAction }.into());
+        let state = self.state_stack.pop().unwrap();
+        self.en_passant = state.en_passant;
+        self.halfmove_clock = state.halfmove_clock;
+        self.castling_rights = state.castling_rights;
+        self.key = state.key;
+        debug_assert_eq!(self.key, self.compute_key());
     }
 
     pub fn push_move(&mut self, m: Move) {
+        self.key_history.push(self.key);
         self.make_move(m);
         self.move_stack.push(m);
         self.move_count += 1;
@@ -1397,6 +2643,7 @@ impl Board {
     pub fn pop_move(&mut self) {
         let m = self.move_stack.pop().unwrap();
         self.unmake_move(m);
+        self.key_history.pop();
     }
 
     pub fn last_move(&self) -> Option<Move> {
@@ -1449,4 +2696,355 @@ impl Board {
     pub fn depth(&self) -> usize {
         self.move_stack.len()
     }
+
+    // number of leaf nodes of the legal move tree, to check move generation against known counts
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+
+        let moves = self.collect_legal_moves(false);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+
+        let mut nodes = 0;
+        for m in moves {
+            self.push_move(m);
+            nodes += self.perft(depth - 1);
+            self.pop_move();
+        }
+        nodes
+    }
+
+    // perft split by root move, to narrow down which move a wrong count comes from
+    pub fn divide(&mut self, depth: u32) -> Vec<(Move, u64)> {
+        let moves = self.collect_legal_moves(false);
+        let mut result = Vec::with_capacity(moves.len());
+        for m in moves {
+            self.push_move(m);
+            let nodes = if depth > 1 { self.perft(depth - 1) } else { 1 };
+            self.pop_move();
+            result.push((m, nodes));
+        }
+        result
+    }
+}
+
+// an infinite search only ends when it's stopped, also when the maximum depth or a mate
+// was reached early, UCI doesn't allow the answer to come before that
+fn wait_for_stop(limits: &SearchLimits, stop: &AtomicBool) {
+    if limits.infinite {
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+    }
+}
+
+// plies taken off late quiet moves, grows with the depth and the move's place in the ordering
+fn late_move_reduction(depth: i32, move_index: usize) -> i32 {
+    (0.75 + (depth as f32).ln() * (move_index as f32).ln() / 2.25) as i32
+}
+
+fn square_index(position: Position) -> usize {
+    (position.x * 8 + position.y) as usize
+}
+
+// true if `p` lies strictly between `a` and `b` on a rank, file or diagonal
+fn is_between(a: Position, b: Position, p: Position) -> bool {
+    let dx = (b.x - a.x).signum();
+    let dy = (b.y - a.y).signum();
+    let mut current = a.offset(dx, dy);
+    while let Some(c) = current {
+        if c == b {
+            return false;
+        }
+        if c == p {
+            return true;
+        }
+        current = c.offset(dx, dy);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // node counts from https://www.chessprogramming.org/Perft_Results
+    fn check_perft(fen: &str, expected: &[u64]) {
+        let mut board = Board::from_fen(fen).unwrap();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            assert_eq!(board.perft(depth as u32 + 1), nodes, "depth {} of {}", depth + 1, fen);
+        }
+        assert_eq!(board.to_fen(), fen);
+    }
+
+    #[test]
+    fn perft_initial_position() {
+        check_perft("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", &[20, 400, 8902, 197281]);
+    }
+
+    #[test]
+    fn perft_kiwipete() {
+        check_perft("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", &[48, 2039, 97862]);
+    }
+
+    #[test]
+    fn perft_position_3() {
+        check_perft("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", &[14, 191, 2812, 43238]);
+    }
+
+    #[test]
+    fn perft_position_4() {
+        check_perft("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", &[6, 264, 9467]);
+    }
+
+    #[test]
+    fn perft_position_4_mirrored() {
+        check_perft("r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1", &[6, 264, 9467]);
+    }
+
+    #[test]
+    fn perft_position_5() {
+        check_perft("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", &[44, 1486, 62379]);
+    }
+
+    #[test]
+    fn perft_position_6() {
+        check_perft("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", &[46, 2079, 89890]);
+    }
+
+    #[test]
+    fn malformed_fen_fields() {
+        let placement = |reason: &str| FenError::PiecePlacement(reason.to_string());
+        let cases = [
+            ("", FenError::MissingField("piece placement")),
+            ("4k3/8/8/8/8/8/8/4K3", FenError::MissingField("side to move")),
+            ("4k3/8/8/8/8/8/8/4K3 w", FenError::MissingField("castling rights")),
+            ("4k3/8/8/8/8/8/8/4K3 w -", FenError::MissingField("en passant")),
+            ("4k3/8/8/8/8/8/4K3 w - - 0 1", placement("expected 8 ranks, found 7")),
+            ("4k3/8/8/8/8/8/8/4X3 w - - 0 1", placement("unknown piece 'X'")),
+            ("4k3/8/8/8/8/8/8/4K4 w - - 0 1", placement("rank 1 has more than 8 cells")),
+            ("4k3/8/8/8/8/8/8/4K2 w - - 0 1", placement("rank 1 does not have 8 cells")),
+            ("9999999999999999/8/8/8/8/8/8/4K3 w - - 0 1", placement("rank 8 has more than 8 cells")),
+            ("4k3/8/8/8/8/8/8/4K3 x - - 0 1", FenError::SideToMove("x".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w KX - 0 1", FenError::CastlingRights("KX".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - e3 0 1", FenError::EnPassant("e3".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - é 0 1", FenError::EnPassant("é".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - -1 1", FenError::HalfmoveClock("-1".to_string())),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 0", FenError::FullmoveNumber("0".to_string())),
+        ];
+        for (fen, error) in cases {
+            assert_eq!(Board::from_fen(fen).err(), Some(error), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn key_matches_fen_after_moves() {
+        let mut board = Board::new_classic_game();
+        for m in ["e2e4", "c7c5", "e4e5", "d7d5", "e1e2"] {
+            let m = board.parse_move(m).unwrap();
+            board.push_move(m);
+        }
+        let fen = board.to_fen();
+        assert_eq!(board.key(), Board::from_fen(&fen).unwrap().key());
+
+        // same pieces, but the en passant square and castling rights differ
+        let other = Board::from_fen("rnbqkbnr/pp2pppp/8/2ppP3/8/8/PPPPKPPP/RNBQ1BNR b KQkq - 0 3").unwrap();
+        assert_ne!(board.key(), other.key());
+
+        for _ in 0..5 {
+            board.pop_move();
+        }
+        assert_eq!(board.key(), Board::new_classic_game().key());
+    }
+
+    #[test]
+    fn search_finds_mate_in_one() {
+        let mut board = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+        let stop = AtomicBool::new(false);
+        let mut last_report = None;
+        let result = board.find_best_move(&SearchLimits::depth(3), &stop, |report| last_report = Some(report.clone()));
+
+        match result {
+            SearchResult::BestMove(m) => assert_eq!(m.to_string(), "d1d8"),
+            SearchResult::GameOver(status) => panic!("unexpected {}", status),
+        }
+        assert_eq!(last_report.unwrap().score.mate_moves(), Some(1));
+    }
+
+    #[test]
+    fn repetition_on_the_last_ply_is_a_draw() {
+        // a queen down, repeating the position is the best white can do
+        let mut board = Board::from_fen("kq6/8/8/8/8/8/8/4K1N1 w - - 0 1").unwrap();
+        for m in ["g1f3", "b8c8", "f3g1", "c8b8"] {
+            let m = board.parse_move(m).unwrap();
+            board.push_move(m);
+        }
+
+        let stop = AtomicBool::new(false);
+        let mut last_report = None;
+        let result = board.find_best_move(&SearchLimits::depth(1), &stop, |report| last_report = Some(report.clone()));
+        assert!(matches!(result, SearchResult::BestMove(m) if m.to_string() == "g1f3"));
+        assert_eq!(last_report.unwrap().score, Score::DRAW);
+    }
+
+    #[test]
+    fn quiescence_sees_the_recapture() {
+        let mut board = Board::from_fen("4k3/8/3p4/4p3/3Q4/8/8/4K3 w - - 0 1").unwrap();
+        let stop = AtomicBool::new(false);
+        let mut last_report = None;
+        let result = board.find_best_move(&SearchLimits::depth(1), &stop, |report| last_report = Some(report.clone()));
+
+        assert!(matches!(result, SearchResult::BestMove(m) if m.to_string() != "d4e5"));
+        let report = last_report.unwrap();
+        assert!(report.qnodes > 0 && report.qnodes < report.nodes);
+    }
+
+    #[test]
+    fn hand_built_boards_only_castle_with_their_pieces() {
+        let board = Board::new_classic_game();
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(board.key(), board.compute_key());
+
+        assert_eq!(Board::new_win_game().to_fen(), "K7/8/7k/8/8/5r2/5r2/RRR2r2 w - - 0 1");
+        let board = Board::new_test_game();
+        assert!(board.to_fen().contains(" w - "));
+        assert_eq!(board.key(), board.compute_key());
+    }
+
+    #[test]
+    fn checkmate_and_stalemate_end_the_game() {
+        let stop = AtomicBool::new(false);
+
+        let mut board = Board::new_classic_game();
+        for m in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+            let m = board.parse_move(m).unwrap();
+            board.push_move(m);
+        }
+        let mate = GameStatus::Checkmate { winner: Color::Black };
+        assert_eq!(board.game_status(), mate);
+        assert_eq!(mate.result(), Some(GameResult::BlackWins));
+        let result = board.find_best_move(&SearchLimits::depth(2), &stop, |_| {});
+        assert!(matches!(result, SearchResult::GameOver(status) if status == mate));
+
+        let mut board = Board::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(board.game_status(), GameStatus::Stalemate);
+        assert_eq!(GameStatus::Stalemate.result(), Some(GameResult::Draw));
+        let result = board.find_best_move(&SearchLimits::depth(2), &stop, |_| {});
+        assert!(matches!(result, SearchResult::GameOver(GameStatus::Stalemate)));
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let mut board = Board::new_classic_game();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+        for (i, m) in shuffle.iter().chain(shuffle.iter()).enumerate() {
+            assert_eq!(board.game_status(), GameStatus::Ongoing, "before move {}", i + 1);
+            let m = board.parse_move(m).unwrap();
+            board.push_move(m);
+        }
+        // the starting position is on the board for the third time
+        assert_eq!(board.repetition_count(), 2);
+        assert_eq!(board.draw_reason(), Some(DrawReason::ThreefoldRepetition));
+        assert_eq!(board.game_status(), GameStatus::Draw(DrawReason::ThreefoldRepetition));
+
+        let stop = AtomicBool::new(false);
+        let result = board.find_best_move(&SearchLimits::depth(2), &stop, |_| {});
+        assert!(matches!(result, SearchResult::BestMove(_)));
+    }
+
+    #[test]
+    fn drawn_positions_are_still_searched() {
+        let stop = AtomicBool::new(false);
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/3NK3 w - - 0 1").unwrap();
+        assert_eq!(board.game_status(), GameStatus::Draw(DrawReason::InsufficientMaterial));
+        let result = board.find_best_move(&SearchLimits::depth(2), &stop, |_| {});
+        assert!(matches!(result, SearchResult::BestMove(_)));
+
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/4K2R w - - 100 80").unwrap();
+        assert_eq!(board.game_status(), GameStatus::Draw(DrawReason::FiftyMoveRule));
+        let result = board.find_best_move(&SearchLimits::depth(2), &stop, |_| {});
+        assert!(matches!(result, SearchResult::BestMove(_)));
+    }
+
+    #[test]
+    fn stopped_search_still_returns_a_move() {
+        let mut board = Board::new_classic_game();
+        let stop = AtomicBool::new(true);
+        let result = board.find_best_move(&SearchLimits::depth(5), &stop, |_| panic!("no iteration should complete"));
+        assert!(matches!(result, SearchResult::BestMove(m) if board.collect_legal_moves(false).contains(&m)));
+    }
+
+    #[test]
+    fn infinite_search_waits_for_stop() {
+        // mate in one, every iteration is over right away
+        let mut board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let limits = SearchLimits {
+            infinite: true,
+            ..SearchLimits::default()
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            thread_stop.store(true, Ordering::Relaxed);
+        });
+
+        let result = board.find_best_move(&limits, &stop, |_| {});
+        assert!(stop.load(Ordering::Relaxed), "answered before being stopped");
+        assert!(matches!(result, SearchResult::BestMove(m) if m.to_string() == "a1a8"));
+        stopper.join().unwrap();
+    }
+
+    #[test]
+    fn gives_check_is_not_counted_as_a_node() {
+        let mut board = Board::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+        let key = board.key;
+        let (mate, quiet) = (board.parse_move("a1a8").unwrap(), board.parse_move("a1a7").unwrap());
+        assert!(board.gives_check(mate));
+        assert!(!board.gives_check(quiet));
+        assert_eq!(board.node_count(), 0);
+        assert!(board.last_move().is_none());
+        assert_eq!(board.key, key);
+    }
+
+    #[test]
+    fn null_move_only_passes_the_turn() {
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2";
+        let mut board = Board::from_fen(fen).unwrap();
+        board.push_move(Move::null());
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 2");
+        assert_eq!(board.key(), board.compute_key());
+        board.pop_move();
+        assert_eq!(board.to_fen(), fen);
+    }
+
+    #[test]
+    fn static_exchange_resolves_recaptures() {
+        let see = |fen: &str, text: &str| {
+            let board = Board::from_fen(fen).unwrap();
+            let m = board.parse_move(text).unwrap();
+            board.static_exchange(&m)
+        };
+
+        let fen = "4k3/8/3p4/2n1p3/3Q4/8/8/3nK3 w - - 0 1";
+        assert_eq!(see(fen, "d4e5"), -800);
+        assert_eq!(see(fen, "d4c5"), -600);
+        // the king can only take undefended pieces
+        assert_eq!(see(fen, "e1d1"), 300);
+
+        // the rooks behind the first ones recapture through them
+        assert_eq!(see("3rk3/3r4/8/3p4/8/3R4/3R4/4K3 w - - 0 1", "d3d5"), 100 - 500 + 500 - 500);
+        assert_eq!(see("3rk3/8/8/3p4/8/3R4/3R4/4K3 w - - 0 1", "d3d5"), 100);
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut board = Board::new_classic_game();
+        let divide = board.divide(3);
+        assert_eq!(divide.len(), 20);
+        assert_eq!(divide.iter().map(|(_, n)| n).sum::<u64>(), board.perft(3));
+    }
 }
@@ -0,0 +1,137 @@
+use crate::piece::Color;
+use std::time::{Duration, Instant};
+
+// deep enough that only another limit or a stop ends the search
+pub const MAX_DEPTH: i32 = 64;
+
+// moves left until the next time control when the clock doesn't say
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+// kept on the clock for the protocol round trip
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+
+// what ends a search, unset limits don't apply
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub wtime: Option<Duration>,
+    pub btime: Option<Duration>,
+    pub winc: Duration,
+    pub binc: Duration,
+    pub movestogo: Option<u32>,
+    // stop once a mate in at most this many moves is found
+    pub mate: Option<u32>,
+    // search until stopped, clocks are ignored
+    pub infinite: bool,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: i32) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Self::default()
+        }
+    }
+
+    pub fn movetime(movetime: Duration) -> Self {
+        Self {
+            movetime: Some(movetime),
+            ..Self::default()
+        }
+    }
+
+    pub fn max_depth(&self) -> i32 {
+        let depth = match (self.depth, self.mate) {
+            (Some(depth), _) => depth,
+            // a mate in n moves is 2n - 1 plies deep, plus one to see the mated side has no moves
+            (None, Some(mate)) => 2 * mate as i32,
+            (None, None) => MAX_DEPTH,
+        };
+        depth.clamp(1, MAX_DEPTH)
+    }
+}
+
+// splits the clock into a time per move
+pub struct TimeManager {
+    start: Instant,
+    // no new iteration is started past this point, it would hardly ever complete
+    soft_limit: Option<Duration>,
+    // the search is stopped at this point, even in the middle of an iteration
+    hard_limit: Option<Duration>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &SearchLimits, color: Color) -> Self {
+        let (time, increment) = match color {
+            Color::White => (limits.wtime, limits.winc),
+            Color::Black => (limits.btime, limits.binc),
+        };
+
+        let (soft_limit, hard_limit) = if limits.infinite {
+            (None, None)
+        } else if let Some(movetime) = limits.movetime {
+            (Some(movetime), Some(movetime))
+        } else if let Some(time) = time {
+            // spread the remaining time over the moves left
+            let moves_to_go = limits.movestogo.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+            let budget = time / moves_to_go + increment / 2;
+            let hard_limit = budget
+                .min(time.saturating_sub(MOVE_OVERHEAD))
+                .max(Duration::from_millis(1));
+            (Some(hard_limit / 2), Some(hard_limit))
+        } else {
+            (None, None)
+        };
+
+        Self {
+            start: Instant::now(),
+            soft_limit,
+            hard_limit,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    pub fn can_start_iteration(&self) -> bool {
+        self.soft_limit.map_or(true, |limit| self.elapsed() < limit)
+    }
+
+    pub fn out_of_time(&self) -> bool {
+        self.hard_limit.map_or(false, |limit| self.elapsed() >= limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_is_split_over_the_moves_to_go() {
+        let limits = SearchLimits {
+            wtime: Some(Duration::from_secs(30)),
+            winc: Duration::from_secs(1),
+            movestogo: Some(10),
+            ..SearchLimits::default()
+        };
+
+        let white = TimeManager::new(&limits, Color::White);
+        assert_eq!(white.hard_limit, Some(Duration::from_millis(3500)));
+        assert_eq!(white.soft_limit, Some(Duration::from_millis(1750)));
+
+        let black = TimeManager::new(&limits, Color::Black);
+        assert_eq!(black.hard_limit, None);
+    }
+
+    #[test]
+    fn mate_search_depth() {
+        let limits = SearchLimits {
+            mate: Some(3),
+            ..SearchLimits::default()
+        };
+        assert_eq!(limits.max_depth(), 6);
+        assert_eq!(SearchLimits::default().max_depth(), MAX_DEPTH);
+    }
+}
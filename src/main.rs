@@ -2,7 +2,7 @@
 #![feature(thread_is_running)]
 
 use std::borrow::BorrowMut;
-use crate::board::{Action, Board, Move, MoveNode};
+use crate::board::{Action, Board, Move};
 use crate::piece::{Color, Piece, Type};
 use rand::Rng;
 use std::cmp::Ordering;
@@ -12,6 +12,7 @@ use std::io::{stdin, stdout, Write};
 use std::mem::size_of;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::cell::{Cell, RefCell};
 use std::pin::Pin;
 use std::sync::mpsc::{sync_channel, SyncSender};
@@ -25,20 +26,27 @@ use sfml::window::mouse::Button;
 use crate::utils::Position;
 
 mod board;
+mod limits;
+mod notation;
 mod piece;
+mod score;
 // mod slotvec;
+mod tt;
+mod uci;
 mod utils;
+mod xboard;
+mod zobrist;
 
-use board::{Command, Response};
+use board::{Command, Pruning, Response, SearchResult};
+use limits::SearchLimits;
+use tt::TableStats;
 
 
-fn run_sfml_gui() {
+fn run_sfml_gui(mut board: Board) {
     use sfml::window::{Style, VideoMode};
     use sfml::graphics::{Sprite, Texture, RenderTarget};
     use std::collections::HashMap;
 
-    let mut board = Board::new_classic_game();
-
     let mut board_copy = board.clone();
 
     let (tx_result, rx_result) = sync_channel::<Option<Move>>(1);
@@ -116,6 +124,11 @@ fn run_sfml_gui() {
 
     let font = sfml::graphics::Font::from_file("ui/fonts/Inconsolata-Regular.ttf").unwrap();
     let mut status_text = sfml::graphics::Text::new("Thinking...", &font, 16);
+    // principal variation of the engine's last search
+    let mut engine_line = String::new();
+    let mut line_text = sfml::graphics::Text::new("", &font, 28);
+    line_text.set_position((8.0, 128.0 * 8.0 - 40.0));
+    line_text.set_fill_color(sfml::graphics::Color::rgb(220, 220, 220));
 
     let mut last_move: Option<Move> = None;
 
@@ -125,6 +138,9 @@ fn run_sfml_gui() {
 
     let (tx_command, rx_command) = sync_channel::<Command>(0);
     let (tx_result, rx_result) = sync_channel::<Response>(0);
+    // ends the engine's search early, it then plays the best move found so far
+    let stop_search = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop_search.clone();
 
     let some_value: u128 = 42;
 
@@ -134,8 +150,7 @@ fn run_sfml_gui() {
                 match cmd {
                     Command::Stop => return,
                     Command::MakeMove(m) => {
-                        println!("Received: {:?}", m);
-                        thread_board.make_move_root(m);
+                        println!("Received: {}", thread_board.move_to_san(&m));
                         thread_board.push_move(m);
                         tx_result.send(Response::Ack).unwrap();
                     }
@@ -145,14 +160,20 @@ fn run_sfml_gui() {
                     }
                     Command::Compute => {
                         println!("Received Compute");
-                        let m = thread_board.find_best_move(6, &rx_command);
-                        println!("Compute result: {:?}", m);
-                        if let Some(m) = m {
-                            println!("Sending...");
-                            tx_result.send(Response::FoundMove(m)).unwrap();
-                            println!("SENT");
-                        } else {
-                            tx_result.send(Response::NoValidMove).unwrap();
+                        let limits = SearchLimits::movetime(std::time::Duration::from_secs(3));
+                        let result = thread_board.find_best_move(&limits, &thread_stop, |report| {
+                            tx_result.send(Response::Report(report.clone())).unwrap();
+                        });
+                        println!("Compute result: {:?}", result);
+                        match result {
+                            SearchResult::BestMove(m) => {
+                                println!("Sending...");
+                                tx_result.send(Response::FoundMove(m)).unwrap();
+                                println!("SENT");
+                            }
+                            SearchResult::GameOver(status) => {
+                                tx_result.send(Response::GameOver(status)).unwrap();
+                            }
                         }
                     }
                 }
@@ -164,6 +185,7 @@ fn run_sfml_gui() {
 
     let mut legal_moves = Vec::new();
     let mut computing = false;
+    let mut game_status = board.game_status();
 
     while window.is_open() {
         while let Some(event) = window.poll_event() {
@@ -171,7 +193,8 @@ fn run_sfml_gui() {
             match event {
                 Event::Closed => window.close(),
                 Event::MouseButtonPressed { button, x, y } => {
-                    if button == Button::LEFT {
+                    // the engine thread only takes new commands once its search is done
+                    if button == Button::LEFT && game_status.has_legal_moves() && !computing {
                         let p = window.map_pixel_to_coords_current_view(Vector2i::new(x, y));
                         let x = p.x as i32 / 128;
                         let y = 7 - p.y as i32 / 128;
@@ -181,6 +204,7 @@ fn run_sfml_gui() {
                                 if let Some(m) = board.move_from_position(v.0 as i8, v.1 as i8, next.0 as i8, next.1 as i8) {
                                     last_move = Some(m);
                                     board.push_move(m);
+                                    game_status = board.game_status();
                                     tx_command.send(Command::MakeMove(m));
                                     sound_move.play();
                                     selected = None;
@@ -189,7 +213,7 @@ fn run_sfml_gui() {
                                     if let Some(piece) = board.piece_at(&Position::new(next.0 as i8, next.1 as i8)) {
                                         if piece.color == board.current_color() {
                                             selected = Some(next);
-                                            legal_moves = board.collect_piece_moves(piece).into_iter().map(|m| m.m).collect();
+                                            legal_moves = board.collect_legal_piece_moves(piece);
                                         }
                                     } else {
                                         selected = None;
@@ -201,7 +225,7 @@ fn run_sfml_gui() {
                             if let Some(piece) = board.piece_at(&Position::new(next.0 as i8, next.1 as i8)) {
                                 if piece.color == board.current_color() {
                                     selected = Some(next);
-                                    legal_moves = board.collect_piece_moves(piece).into_iter().map(|m| m.m).collect();
+                                    legal_moves = board.collect_legal_piece_moves(piece);
                                 }
                             } else {
                                 selected = None;
@@ -217,16 +241,21 @@ fn run_sfml_gui() {
                     }
                 }
                 Event::KeyPressed { code, alt, ctrl, shift, system } => {
-                    if code == Key::LEFT {
+                    if code == Key::ESCAPE && computing {
+                        stop_search.store(true, AtomicOrdering::Relaxed);
+                    } else if code == Key::LEFT && !computing {
                         board.pop_move();
+                        game_status = board.game_status();
                         tx_command.send(Command::Undo);
                         if let Some(m) = board.last_move() {
                             last_move = Some(m);
                         } else {
                             last_move = None;
                         }
-                    } else if code == Key::SPACE {
+                    } else if code == Key::SPACE && game_status.has_legal_moves() && !computing {
+                        stop_search.store(false, AtomicOrdering::Relaxed);
                         tx_command.send(Command::Compute);
+                        engine_line.clear();
                         compute_start = std::time::Instant::now();
                         computing = true;
                     }
@@ -239,17 +268,23 @@ fn run_sfml_gui() {
             println!("Received: {:?}", response);
             match response {
                 Response::Ack => {}
+                Response::Report(report) => {
+                    engine_line = format!("depth {}  {}  {}", report.depth, report.score, board.line_to_san(&report.pv).join(" "));
+                    println!("{}  ({} nodes, {} nps)", engine_line, report.nodes, report.nps());
+                }
                 Response::FoundMove(m) => {
-                    println!("Received move: {:?}", m);
+                    println!("Received move: {}", board.move_to_san(&m));
                     computing = false;
                     board.push_move(m);
+                    game_status = board.game_status();
                     tx_command.send(Command::MakeMove(m));
                     last_move = Some(m);
                     sound_move.play();
                     rx_result.recv();
                 }
-                Response::NoValidMove => {
+                Response::GameOver(status) => {
                     computing = false;
+                    game_status = status;
                 }
             }
         }
@@ -280,14 +315,17 @@ fn run_sfml_gui() {
                 Action::Capture { piece, target } => {
                     Some((piece.position.x, piece.position.y, target.position.x, target.position.y))
                 }
-                Action::Promote { old_piece, new_piece } => {
+                Action::Promote { old_piece, new_piece } | Action::CapturePromote { old_piece, new_piece, .. } => {
                     Some((old_piece.position.x, old_piece.position.y, new_piece.position.x, new_piece.position.y))
                 }
-                Action::CastleQueenSide => {
-                    None
+                Action::EnPassant { piece, to, .. } => {
+                    Some((piece.position.x, piece.position.y, to.x, to.y))
                 }
-                Action::CastleKingSide => {
-                    None
+                Action::CastleQueenSide { color } => {
+                    Some((4, color.home_rank(), 2, color.home_rank()))
+                }
+                Action::CastleKingSide { color } => {
+                    Some((4, color.home_rank(), 6, color.home_rank()))
                 }
             };
 
@@ -347,21 +385,12 @@ fn run_sfml_gui() {
                 Action::NoAction => { unreachable!() }
                 Action::Move { to, .. } => (to.position.x, to.position.y),
                 Action::Capture { target, .. } => (target.position.x, target.position.y),
-                Action::Promote { new_piece, .. } => (new_piece.position.x, new_piece.position.y),
-                Action::CastleKingSide => {
-                    if board.current_color() == Color::White {
-                        (6, 0)
-                    } else {
-                        (6, 7)
-                    }
-                }
-                Action::CastleQueenSide => {
-                    if board.current_color() == Color::White {
-                        (2, 0)
-                    } else {
-                        (2, 7)
-                    }
+                Action::Promote { new_piece, .. } | Action::CapturePromote { new_piece, .. } => {
+                    (new_piece.position.x, new_piece.position.y)
                 }
+                Action::EnPassant { to, .. } => (to.x, to.y),
+                Action::CastleKingSide { color } => (6, color.home_rank()),
+                Action::CastleQueenSide { color } => (2, color.home_rank()),
             };
             let px = x as f32 * 128.0;
             let py = (7 - y) as f32 * 128.0;
@@ -385,11 +414,149 @@ fn run_sfml_gui() {
             // }
         }
 
+        if !engine_line.is_empty() {
+            line_text.set_string(&engine_line);
+            window.draw(&line_text);
+        }
+
+        if let Some(result) = game_status.result() {
+            status_text.set_string(&format!("{}  {}", result, game_status));
+            status_text.set_character_size(42);
+            status_text.set_fill_color(sfml::graphics::Color::rgb(255, 200, 50));
+            window.draw(&status_text);
+        }
+
         window.display();
     }
 }
 
+// `--perft <depth> [fen]`: prints the node count below each root move, then the total
+fn run_perft() {
+    let mut args = std::env::args().skip(2);
+    let depth = match args.next().and_then(|d| d.parse::<u32>().ok()) {
+        Some(depth) if depth > 0 => depth,
+        _ => {
+            eprintln!("Usage: --perft <depth> [fen]");
+            return;
+        }
+    };
+    let mut board = match args.next() {
+        Some(fen) => match Board::from_fen(&fen) {
+            Ok(board) => board,
+            Err(e) => {
+                eprintln!("Invalid FEN: {}", e);
+                return;
+            }
+        },
+        None => Board::new_classic_game(),
+    };
+
+    let start = std::time::Instant::now();
+    let mut total = 0;
+    for (m, nodes) in board.divide(depth) {
+        println!("{}: {}", m, nodes);
+        total += nodes;
+    }
+    println!();
+    println!("Nodes searched: {}", total);
+    println!("Time: {} ms", start.elapsed().as_millis());
+}
+
+const BENCH_POSITIONS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "8/8/8/4k3/8/8/8/4K2R w K - 0 1",
+];
+
+// `--bench [depth] [technique...]`: searches a fixed set of positions, the node count
+// shows the effect of search changes and the speed the effect of everything else.
+// The techniques listed (null-move, lmr, rfp, razoring, delta) are switched off,
+// qchecks switches on quiet checks in the quiescence search.
+fn run_bench() {
+    let mut args = std::env::args().skip(2);
+    let depth = args.next().and_then(|d| d.parse::<i32>().ok()).unwrap_or(4);
+
+    let mut pruning = Pruning::default();
+    for technique in args {
+        match technique.as_str() {
+            "null-move" => pruning.null_move = false,
+            "lmr" => pruning.late_move_reductions = false,
+            "rfp" => pruning.reverse_futility = false,
+            "razoring" => pruning.razoring = false,
+            "delta" => pruning.delta = false,
+            "qchecks" => pruning.quiet_checks = true,
+            _ => {
+                eprintln!("unknown technique {}, expected null-move, lmr, rfp, razoring, delta or qchecks", technique);
+                return;
+            }
+        }
+    }
+
+    let start = std::time::Instant::now();
+    let mut total_nodes = 0;
+    let mut total_qnodes = 0;
+    let mut total_tt = TableStats::default();
+    for fen in BENCH_POSITIONS {
+        let mut board = Board::from_fen(fen).unwrap();
+        board.set_pruning(pruning);
+        let stop = AtomicBool::new(false);
+        let (mut nodes, mut qnodes, mut tt) = (0, 0, TableStats::default());
+        let result = board.find_best_move(&SearchLimits::depth(depth), &stop, |report| {
+            nodes = report.nodes;
+            qnodes = report.qnodes;
+            tt = report.tt;
+        });
+        if let SearchResult::BestMove(m) = result {
+            println!("{}: {} ({} nodes, {} in quiescence)", fen, board.move_to_san(&m), nodes, qnodes);
+        }
+        total_nodes += nodes;
+        total_qnodes += qnodes;
+        total_tt.probes += tt.probes;
+        total_tt.hits += tt.hits;
+        total_tt.stores += tt.stores;
+        total_tt.overwrites += tt.overwrites;
+    }
+
+    let elapsed = start.elapsed();
+    println!();
+    println!("Nodes searched: {} ({} in quiescence)", total_nodes, total_qnodes);
+    println!("Transposition table: {}", total_tt);
+    println!("Time: {} ms", elapsed.as_millis());
+    println!("Nodes/second: {}", (total_nodes as f64 / elapsed.as_secs_f64().max(0.001)) as u64);
+}
+
 fn main() {
-    run_sfml_gui();
+    // an optional FEN argument sets up the starting position, e.g. with black to move
+    let board = match std::env::args().nth(1) {
+        Some(arg) if arg == "--uci" => {
+            uci::run();
+            return;
+        }
+        Some(arg) if arg == "--xboard" => {
+            xboard::run();
+            return;
+        }
+        Some(arg) if arg == "--perft" => {
+            run_perft();
+            return;
+        }
+        Some(arg) if arg == "--bench" => {
+            run_bench();
+            return;
+        }
+        Some(fen) => match Board::from_fen(&fen) {
+            Ok(board) => board,
+            Err(e) => {
+                eprintln!("Invalid FEN: {}", e);
+                return;
+            }
+        },
+        None => Board::new_classic_game(),
+    };
+
+    run_sfml_gui(board);
     return;
 }
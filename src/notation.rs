@@ -0,0 +1,288 @@
+use crate::board::{Action, Board, Move};
+use crate::piece::Type;
+use crate::utils::Position;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MoveParseError {
+    Syntax(String),
+    IllegalMove(String),
+    Ambiguous(String),
+}
+
+impl std::fmt::Display for MoveParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MoveParseError::Syntax(s) => write!(f, "cannot parse move '{}'", s),
+            MoveParseError::IllegalMove(s) => write!(f, "illegal move '{}'", s),
+            MoveParseError::Ambiguous(s) => write!(f, "ambiguous move '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for MoveParseError {}
+
+impl Board {
+    // standard algebraic notation of a legal move in the current position, e.g. "Nbd7" or "exd8=Q+"
+    pub fn move_to_san(&mut self, m: &Move) -> String {
+        let mut san = match m.action {
+            Action::CastleKingSide { .. } => "O-O".to_string(),
+            Action::CastleQueenSide { .. } => "O-O-O".to_string(),
+            _ => {
+                let (piece, to) = self.move_endpoints(m).unwrap();
+                let mut san = String::new();
+
+                if piece.t == Type::Pawn {
+                    if m.is_capture() {
+                        san.push(file_char(piece.position.x));
+                    }
+                } else {
+                    san.push(piece.t.character());
+
+                    // other pieces of the same kind that can reach the same cell
+                    let others: Vec<Position> = self
+                        .collect_legal_moves(false)
+                        .iter()
+                        .filter_map(|other| self.move_endpoints(other))
+                        .filter(|(other, other_to)| {
+                            other.t == piece.t && *other_to == to && other.position != piece.position
+                        })
+                        .map(|(other, _)| other.position)
+                        .collect();
+
+                    if !others.is_empty() {
+                        if others.iter().all(|p| p.x != piece.position.x) {
+                            san.push(file_char(piece.position.x));
+                        } else if others.iter().all(|p| p.y != piece.position.y) {
+                            san.push(rank_char(piece.position.y));
+                        } else {
+                            san.push_str(&piece.position.to_string());
+                        }
+                    }
+                }
+
+                if m.is_capture() {
+                    san.push('x');
+                }
+                san.push_str(&to.to_string());
+
+                if let Some(t) = m.promotion() {
+                    san.push('=');
+                    san.push(t.character());
+                }
+                san
+            }
+        };
+
+        self.push_move(*m);
+        if self.is_in_check(self.current_color()) {
+            san.push(if self.collect_legal_moves(false).is_empty() { '#' } else { '+' });
+        }
+        self.pop_move();
+
+        san
+    }
+
+    // SAN of a sequence of moves played from the current position, e.g. a principal variation
+    pub fn line_to_san(&mut self, line: &[Move]) -> Vec<String> {
+        let mut sans = Vec::with_capacity(line.len());
+        for m in line {
+            sans.push(self.move_to_san(m));
+            self.push_move(*m);
+        }
+        for _ in line {
+            self.pop_move();
+        }
+        sans
+    }
+
+    // long algebraic notation as used by UCI, e.g. "e2e4", "e7e8q" or "e1g1"
+    pub fn parse_move(&self, text: &str) -> Result<Move, MoveParseError> {
+        let syntax_error = || MoveParseError::Syntax(text.to_string());
+
+        let text = text.trim();
+        if !text.is_ascii() || (text.len() != 4 && text.len() != 5) {
+            return Err(syntax_error());
+        }
+
+        let from = Position::parse(&text[0..2]).ok_or_else(syntax_error)?;
+        let to = Position::parse(&text[2..4]).ok_or_else(syntax_error)?;
+        let promotion = match text[4..].chars().next() {
+            Some(c) if "qrbn".contains(c) => Type::from_char(c),
+            Some(_) => return Err(syntax_error()),
+            None => None,
+        };
+
+        self.collect_legal_moves(false)
+            .into_iter()
+            .find(|m| match self.move_endpoints(m) {
+                Some((piece, m_to)) => piece.position == from && m_to == to && m.promotion() == promotion,
+                None => false,
+            })
+            .ok_or_else(|| MoveParseError::IllegalMove(text.to_string()))
+    }
+
+    pub fn parse_san(&self, san: &str) -> Result<Move, MoveParseError> {
+        let syntax_error = || MoveParseError::Syntax(san.to_string());
+        let legal_moves = self.collect_legal_moves(false);
+
+        let text = san.trim().trim_end_matches(|c| matches!(c, '+' | '#' | '!' | '?'));
+
+        let castle = match text {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(king_side) = castle {
+            return legal_moves
+                .into_iter()
+                .find(|m| match m.action {
+                    Action::CastleKingSide { .. } => king_side,
+                    Action::CastleQueenSide { .. } => !king_side,
+                    _ => false,
+                })
+                .ok_or_else(|| MoveParseError::IllegalMove(san.to_string()));
+        }
+
+        let mut chars: Vec<char> = text.chars().collect();
+
+        // promotion suffix, with or without '='
+        let mut promotion = None;
+        if let Some(&last) = chars.last() {
+            if "QRBN".contains(last) && chars.len() > 2 {
+                promotion = Type::from_char(last);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+
+        let t = match chars.first() {
+            Some(c) if "NBRQK".contains(*c) => {
+                let t = Type::from_char(*c).unwrap();
+                chars.remove(0);
+                t
+            }
+            Some(_) => Type::Pawn,
+            None => return Err(syntax_error()),
+        };
+
+        if chars.len() < 2 {
+            return Err(syntax_error());
+        }
+        let to: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to = Position::parse(&to).ok_or_else(syntax_error)?;
+
+        let mut from_x = None;
+        let mut from_y = None;
+        for c in chars {
+            match c {
+                'a'..='h' => from_x = Some(c as i8 - 'a' as i8),
+                '1'..='8' => from_y = Some(c as i8 - '1' as i8),
+                'x' | ':' | '-' => {}
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        let candidates: Vec<Move> = legal_moves
+            .into_iter()
+            .filter(|m| match self.move_endpoints(m) {
+                Some((piece, m_to)) => {
+                    piece.t == t
+                        && m_to == to
+                        && from_x.map_or(true, |x| piece.position.x == x)
+                        && from_y.map_or(true, |y| piece.position.y == y)
+                        && !matches!(m.action, Action::CastleKingSide { .. } | Action::CastleQueenSide { .. })
+                }
+                None => false,
+            })
+            .filter(|m| m.promotion() == promotion || (promotion.is_none() && m.promotion() == Some(Type::Queen)))
+            .collect();
+
+        match candidates.len() {
+            0 => Err(MoveParseError::IllegalMove(san.to_string())),
+            1 => Ok(candidates[0]),
+            _ => Err(MoveParseError::Ambiguous(san.to_string())),
+        }
+    }
+}
+
+fn file_char(x: i8) -> char {
+    (b'a' + x as u8) as char
+}
+
+fn rank_char(y: i8) -> char {
+    (b'1' + y as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SAN of a move given in UCI notation
+    fn san(fen: &str, text: &str) -> String {
+        let mut board = Board::from_fen(fen).unwrap();
+        let m = board.parse_move(text).unwrap();
+        board.move_to_san(&m)
+    }
+
+    #[test]
+    fn san_disambiguation() {
+        let fen = "rnbqkb1r/ppp1pppp/5n2/3p4/3P4/5N2/PPP1PPPP/RNBQKB1R b KQkq - 2 3";
+        assert_eq!(san(fen, "b8d7"), "Nbd7");
+        assert_eq!(san(fen, "f6d7"), "Nfd7");
+
+        let fen = "k7/8/8/8/8/4R3/8/4R1K1 w - - 0 1";
+        assert_eq!(san(fen, "e1e2"), "R1e2");
+        assert_eq!(san(fen, "e3e2"), "R3e2");
+    }
+
+    #[test]
+    fn san_special_moves() {
+        assert_eq!(san("3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1", "e7d8q"), "exd8=Q+");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1g1"), "O-O");
+        assert_eq!(san("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "e1c1"), "O-O-O");
+        assert_eq!(san("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2", "e5d6"), "exd6");
+        assert_eq!(san("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8"), "Rd8#");
+    }
+
+    #[test]
+    fn parse_san_moves() {
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(board.parse_san("O-O").unwrap().to_string(), "e1g1");
+        assert_eq!(board.parse_san("O-O-O").unwrap().to_string(), "e1c1");
+
+        let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2").unwrap();
+        assert!(matches!(board.parse_san("exd6").unwrap().action, Action::EnPassant { .. }));
+
+        let board = Board::from_fen("3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.parse_san("exd8=Q+").unwrap().to_string(), "e7d8q");
+        assert_eq!(board.parse_san("exd8N").unwrap().to_string(), "e7d8n");
+
+        let board = Board::from_fen("rnbqkb1r/ppp1pppp/5n2/3p4/3P4/5N2/PPP1PPPP/RNBQKB1R b KQkq - 2 3").unwrap();
+        assert_eq!(board.parse_san("Nbd7").unwrap().to_string(), "b8d7");
+        assert_eq!(board.parse_san("Nd7"), Err(MoveParseError::Ambiguous("Nd7".to_string())));
+        assert_eq!(board.parse_san("Qh4+"), Err(MoveParseError::IllegalMove("Qh4+".to_string())));
+        assert_eq!(board.parse_san("Nz9"), Err(MoveParseError::Syntax("Nz9".to_string())));
+    }
+
+    #[test]
+    fn parse_uci_moves() {
+        let board = Board::from_fen("3k4/4P3/8/8/8/8/8/R3K2R w KQ - 0 1").unwrap();
+        // the promotion has to be named
+        assert_eq!(board.parse_move("e7e8"), Err(MoveParseError::IllegalMove("e7e8".to_string())));
+        let m = board.parse_move("e7e8n").unwrap();
+        assert_eq!(m.promotion(), Some(Type::Knight));
+        assert_eq!(m.to_string(), "e7e8n");
+        assert_eq!(board.parse_move("e7e8r").unwrap().promotion(), Some(Type::Rook));
+
+        // castling is written as the king moving two cells
+        assert!(matches!(board.parse_move("e1g1").unwrap().action, Action::CastleKingSide { .. }));
+        assert!(matches!(board.parse_move("e1c1").unwrap().action, Action::CastleQueenSide { .. }));
+
+        assert_eq!(board.parse_move("e9e8"), Err(MoveParseError::Syntax("e9e8".to_string())));
+        assert_eq!(board.parse_move("e7e8k"), Err(MoveParseError::Syntax("e7e8k".to_string())));
+        assert_eq!(board.parse_move("e1"), Err(MoveParseError::Syntax("e1".to_string())));
+        assert_eq!(board.parse_move("a1b2"), Err(MoveParseError::IllegalMove("a1b2".to_string())));
+    }
+}
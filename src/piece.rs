@@ -6,6 +6,22 @@ pub enum Color {
     Black,
 }
 
+impl Color {
+    pub fn home_rank(&self) -> i8 {
+        match self {
+            Color::White => 0,
+            Color::Black => 7,
+        }
+    }
+
+    pub fn opposite(&self) -> Self {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Type {
     Pawn,
@@ -16,6 +32,31 @@ pub enum Type {
     King,
 }
 
+impl Type {
+    pub fn from_char(c: char) -> Option<Self> {
+        match c.to_ascii_lowercase() {
+            'p' => Some(Type::Pawn),
+            'b' => Some(Type::Bishop),
+            'n' => Some(Type::Knight),
+            'r' => Some(Type::Rook),
+            'q' => Some(Type::Queen),
+            'k' => Some(Type::King),
+            _ => None,
+        }
+    }
+
+    pub fn character(&self) -> char {
+        match self {
+            Type::Pawn => 'P',
+            Type::Bishop => 'B',
+            Type::Knight => 'N',
+            Type::Rook => 'R',
+            Type::Queen => 'Q',
+            Type::King => 'K',
+        }
+    }
+}
+
 pub type PieceIndex = u8;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -0,0 +1,137 @@
+use std::ops::{Add, Neg, Sub};
+
+// highest score, only used as the bounds of a full window
+const INFINITE: i16 = 32000;
+// checkmate on the board, mates found deeper in the tree score lower
+const MATE: i16 = 30000;
+// anything beyond this is a mate, as no line is searched this deep
+const MATE_BOUND: i16 = MATE - 1000;
+
+// evaluation from the side to move's point of view: centipawns, or a mate
+// encoded by its distance in plies from the root
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Score(i16);
+
+impl Score {
+    pub const DRAW: Score = Score(0);
+    pub const INFINITE: Score = Score(INFINITE);
+
+    pub fn centipawns(value: i16) -> Self {
+        Score(value.clamp(-MATE_BOUND, MATE_BOUND))
+    }
+
+    // the side to move mates in `ply` plies
+    pub fn mate_in(ply: usize) -> Self {
+        Score(MATE - ply as i16)
+    }
+
+    // the side to move is mated in `ply` plies
+    pub fn mated_in(ply: usize) -> Self {
+        Score(-MATE + ply as i16)
+    }
+
+    pub fn value(self) -> i16 {
+        self.0
+    }
+
+    pub fn is_mate(self) -> bool {
+        self.0.abs() > MATE_BOUND && self.0.abs() <= MATE
+    }
+
+    // full moves until mate, negative when the side to move gets mated
+    pub fn mate_moves(self) -> Option<i32> {
+        if !self.is_mate() {
+            None
+        } else if self.0 > 0 {
+            Some((MATE - self.0 + 1) as i32 / 2)
+        } else {
+            Some(-((MATE + self.0) as i32 / 2))
+        }
+    }
+
+    // mates are stored relative to the node instead of the root, so they stay
+    // correct when the position is reached at another ply
+    pub fn to_tt(self, ply: usize) -> Self {
+        if !self.is_mate() {
+            self
+        } else if self.0 > 0 {
+            Score(self.0 + ply as i16)
+        } else {
+            Score(self.0 - ply as i16)
+        }
+    }
+
+    pub fn from_tt(self, ply: usize) -> Self {
+        if !self.is_mate() {
+            self
+        } else if self.0 > 0 {
+            Score(self.0 - ply as i16)
+        } else {
+            Score(self.0 + ply as i16)
+        }
+    }
+}
+
+impl Neg for Score {
+    type Output = Score;
+
+    fn neg(self) -> Score {
+        Score(-self.0)
+    }
+}
+
+// margins around a score, e.g. for search windows, saturating at the infinite bounds
+impl Add<i16> for Score {
+    type Output = Score;
+
+    fn add(self, margin: i16) -> Score {
+        Score(self.0.saturating_add(margin).clamp(-INFINITE, INFINITE))
+    }
+}
+
+impl Sub<i16> for Score {
+    type Output = Score;
+
+    fn sub(self, margin: i16) -> Score {
+        Score(self.0.saturating_sub(margin).clamp(-INFINITE, INFINITE))
+    }
+}
+
+// "+0.25" in pawns, or "#3" and "#-2" for mates
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.mate_moves() {
+            Some(moves) => write!(f, "#{}", moves),
+            None => write!(f, "{:+.2}", self.0 as f32 / 100.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_distance() {
+        assert_eq!(Score::mate_in(1).mate_moves(), Some(1));
+        assert_eq!(Score::mate_in(5).mate_moves(), Some(3));
+        assert_eq!(Score::mated_in(4).mate_moves(), Some(-2));
+        assert_eq!((-Score::mated_in(3)).mate_moves(), Some(2));
+        assert_eq!(Score::centipawns(250).mate_moves(), None);
+        assert!(Score::mate_in(3) > Score::mate_in(7));
+        assert!(Score::mated_in(2) < Score::centipawns(-900));
+        assert_eq!(Score::mate_in(3).to_string(), "#2");
+        assert_eq!(Score::centipawns(-25).to_string(), "-0.25");
+    }
+
+    #[test]
+    fn tt_adjustment_keeps_distance_to_the_node() {
+        // a mate in 5 plies from the root, stored at ply 2 is a mate in 3 from that node
+        let stored = Score::mate_in(5).to_tt(2);
+        assert_eq!(stored, Score::mate_in(3));
+        // reached again at ply 4, it's a mate in 7 from the root
+        assert_eq!(stored.from_tt(4), Score::mate_in(7));
+        assert_eq!(Score::mated_in(6).to_tt(2).from_tt(2), Score::mated_in(6));
+        assert_eq!(Score::centipawns(40).to_tt(9), Score::centipawns(40));
+    }
+}
@@ -0,0 +1,143 @@
+use crate::board::Move;
+use crate::score::Score;
+
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+// how the stored score relates to the real value of the position
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Bound {
+    Exact,
+    // the search failed high, the real score is at least this
+    Lower,
+    // the search failed low, the real score is at most this
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: Score,
+    pub best_move: Option<Move>,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TableStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub stores: u64,
+    // stores that replaced an entry of another position
+    pub overwrites: u64,
+}
+
+impl std::fmt::Display for TableStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "probes {} hits {} ({}%) stores {} overwrites {}",
+            self.probes,
+            self.hits,
+            self.hits * 100 / self.probes.max(1),
+            self.stores,
+            self.overwrites
+        )
+    }
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Option<Entry>>,
+    stats: TableStats,
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let len = (megabytes * 1024 * 1024 / std::mem::size_of::<Option<Entry>>()).max(1);
+        Self {
+            entries: vec![None; len],
+            stats: TableStats::default(),
+        }
+    }
+
+    pub fn resize(&mut self, megabytes: usize) {
+        *self = Self::new(megabytes);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|e| *e = None);
+        self.stats = TableStats::default();
+    }
+
+    fn index(&self, key: u64) -> usize {
+        // maps the key onto the table without needing a power of two size
+        ((key as u128 * self.entries.len() as u128) >> 64) as usize
+    }
+
+    pub fn probe(&mut self, key: u64) -> Option<Entry> {
+        self.stats.probes += 1;
+        match self.entries[self.index(key)] {
+            Some(entry) if entry.key == key => {
+                self.stats.hits += 1;
+                Some(entry)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn store(&mut self, key: u64, depth: u8, bound: Bound, score: Score, best_move: Option<Move>) {
+        let index = self.index(key);
+        let slot = &mut self.entries[index];
+
+        // a shallower result for the same position doesn't replace a deeper one,
+        // but other positions always take the slot
+        let best_move = match slot {
+            Some(entry) if entry.key == key => {
+                if depth < entry.depth && bound != Bound::Exact {
+                    return;
+                }
+                best_move.or(entry.best_move)
+            }
+            Some(_) => {
+                self.stats.overwrites += 1;
+                best_move
+            }
+            None => best_move,
+        };
+
+        *slot = Some(Entry { key, depth, bound, score, best_move });
+        self.stats.stores += 1;
+    }
+
+    pub fn stats(&self) -> TableStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = TableStats::default();
+    }
+
+    // permille of the first thousand slots in use, as reported by UCI's hashfull
+    pub fn hashfull(&self) -> u32 {
+        let sample = self.entries.len().min(1000);
+        let used = self.entries[..sample].iter().filter(|e| e.is_some()).count();
+        (used * 1000 / sample) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deeper_entries_are_kept() {
+        let mut tt = TranspositionTable::new(1);
+        tt.store(42, 5, Bound::Lower, Score::centipawns(100), None);
+        tt.store(42, 3, Bound::Upper, Score::centipawns(-50), None);
+        let entry = tt.probe(42).unwrap();
+        assert_eq!((entry.depth, entry.bound, entry.score), (5, Bound::Lower, Score::centipawns(100)));
+        assert!(tt.probe(43).is_none());
+
+        let stats = tt.stats();
+        assert_eq!((stats.probes, stats.hits, stats.stores), (2, 1, 1));
+    }
+}
@@ -0,0 +1,229 @@
+use crate::board::{Board, GameStatus, Pruning, SearchReport, SearchResult};
+use crate::limits::SearchLimits;
+use crate::tt;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const ENGINE_NAME: &str = "chess-rs";
+const ENGINE_AUTHOR: &str = "plichard";
+
+struct Search {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+pub fn run() {
+    let mut board = Board::new_classic_game();
+    let mut search: Option<Search> = None;
+    // kept across positions, a new board would otherwise start with an empty table
+    let tt = board.transposition_table();
+    let mut pruning = Pruning::default();
+
+    let stdin = std::io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("uci") => {
+                println!("id name {}", ENGINE_NAME);
+                println!("id author {}", ENGINE_AUTHOR);
+                println!("option name Hash type spin default {} min 1 max 4096", tt::DEFAULT_SIZE_MB);
+                for (name, default) in PRUNING_OPTIONS {
+                    println!("option name {} type check default {}", name, default);
+                }
+                println!("uciok");
+            }
+            Some("isready") => println!("readyok"),
+            Some("setoption") => {
+                stop_search(&mut search);
+                // setoption name <id> value <x>
+                let tokens: Vec<_> = tokens.collect();
+                if tokens.len() == 4 && tokens[0] == "name" && tokens[2] == "value" {
+                    match set_option(tokens[1], tokens[3], &mut board, &mut pruning) {
+                        Ok(()) => board.set_pruning(pruning),
+                        Err(e) => println!("info string {}", e),
+                    }
+                }
+            }
+            Some("ucinewgame") => {
+                stop_search(&mut search);
+                tt.lock().unwrap().clear();
+                board = Board::new_classic_game();
+                board.set_transposition_table(tt.clone());
+                board.set_pruning(pruning);
+            }
+            Some("position") => {
+                stop_search(&mut search);
+                match parse_position(&tokens.collect::<Vec<_>>()) {
+                    Ok(position) => {
+                        board = position;
+                        board.set_transposition_table(tt.clone());
+                        board.set_pruning(pruning);
+                    }
+                    Err(e) => println!("info string {}", e),
+                }
+            }
+            Some("go") => {
+                stop_search(&mut search);
+                let limits = parse_go(&tokens.collect::<Vec<_>>());
+                search = Some(start_search(board.clone(), limits));
+            }
+            Some("stop") => stop_search(&mut search),
+            // the predicted move was played, the move found while pondering is answered right away
+            Some("ponderhit") => stop_search(&mut search),
+            Some("quit") => {
+                stop_search(&mut search);
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+// check options switching the selective search techniques, to measure what each is worth
+const PRUNING_OPTIONS: [(&str, bool); 6] = [
+    ("NullMove", true),
+    ("LateMoveReductions", true),
+    ("ReverseFutility", true),
+    ("Razoring", true),
+    ("DeltaPruning", true),
+    ("QuiescenceChecks", false),
+];
+
+// the board shares its table with every later position, resizing it applies to all of them
+fn set_option(name: &str, value: &str, board: &mut Board, pruning: &mut Pruning) -> Result<(), String> {
+    if name.eq_ignore_ascii_case("hash") {
+        return match value.parse::<usize>() {
+            Ok(megabytes) if (1..=4096).contains(&megabytes) => {
+                board.set_hash_size(megabytes);
+                Ok(())
+            }
+            _ => Err(format!("invalid hash size {}", value)),
+        };
+    }
+
+    let enabled = match value {
+        "true" => true,
+        "false" => false,
+        _ => return Err(format!("invalid value {} for option {}", value, name)),
+    };
+    match name.to_ascii_lowercase().as_str() {
+        "nullmove" => pruning.null_move = enabled,
+        "latemovereductions" => pruning.late_move_reductions = enabled,
+        "reversefutility" => pruning.reverse_futility = enabled,
+        "razoring" => pruning.razoring = enabled,
+        "deltapruning" => pruning.delta = enabled,
+        "quiescencechecks" => pruning.quiet_checks = enabled,
+        _ => return Err(format!("unknown option {}", name)),
+    }
+    Ok(())
+}
+
+// position [startpos | fen <fen>] [moves <move>...]
+fn parse_position(tokens: &[&str]) -> Result<Board, String> {
+    let moves_index = tokens.iter().position(|t| *t == "moves").unwrap_or(tokens.len());
+
+    let mut board = match tokens.first() {
+        Some(&"startpos") => Board::new_classic_game(),
+        Some(&"fen") => Board::from_fen(&tokens[1..moves_index].join(" ")).map_err(|e| e.to_string())?,
+        _ => return Err("expected startpos or fen".to_string()),
+    };
+
+    for text in tokens.iter().skip(moves_index + 1) {
+        let m = board.parse_move(text).map_err(|e| e.to_string())?;
+        board.push_move(m);
+    }
+
+    Ok(board)
+}
+
+// go [depth <plies>] [nodes <n>] [mate <moves>] [movetime <ms>] [wtime <ms>] [btime <ms>]
+//    [winc <ms>] [binc <ms>] [movestogo <n>] [infinite] [ponder]
+fn parse_go(tokens: &[&str]) -> SearchLimits {
+    let value = |name: &str| -> Option<u64> {
+        let index = tokens.iter().position(|t| *t == name)?;
+        tokens.get(index + 1)?.parse().ok()
+    };
+    let millis = |name: &str| value(name).map(Duration::from_millis);
+
+    SearchLimits {
+        depth: value("depth").map(|depth| depth as i32),
+        nodes: value("nodes"),
+        movetime: millis("movetime"),
+        wtime: millis("wtime"),
+        btime: millis("btime"),
+        winc: millis("winc").unwrap_or_default(),
+        binc: millis("binc").unwrap_or_default(),
+        movestogo: value("movestogo").map(|moves| moves as u32),
+        mate: value("mate").map(|moves| moves as u32),
+        // pondering runs until ponderhit or stop, like an infinite search
+        infinite: tokens.contains(&"infinite") || tokens.contains(&"ponder"),
+    }
+}
+
+fn start_search(mut board: Board, limits: SearchLimits) -> Search {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread_stop = stop.clone();
+
+    // a claimable draw is only reported, the GUI decides whether to play on
+    let status = board.game_status();
+    if let GameStatus::Draw(_) = status {
+        println!("info string {}", status);
+    }
+
+    let handle = std::thread::spawn(move || {
+        let mut ponder = None;
+        let result = board.find_best_move(&limits, &thread_stop, |report| {
+            print_info(report);
+            ponder = report.pv.get(1).copied();
+        });
+
+        match result {
+            SearchResult::BestMove(m) => match ponder {
+                Some(ponder) => println!("bestmove {} ponder {}", m, ponder),
+                None => println!("bestmove {}", m),
+            },
+            // checkmate or stalemate, there is no move to answer with
+            SearchResult::GameOver(status) => {
+                println!("info string {}", status);
+                println!("bestmove 0000");
+            }
+        }
+    });
+
+    Search { stop, handle }
+}
+
+fn print_info(report: &SearchReport) {
+    let score = match report.score.mate_moves() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", report.score.value()),
+    };
+    let pv: Vec<String> = report.pv.iter().map(|m| m.to_string()).collect();
+    println!(
+        "info depth {} seldepth {} score {} nodes {} nps {} time {} hashfull {} pv {}",
+        report.depth,
+        report.seldepth,
+        score,
+        report.nodes,
+        report.nps(),
+        report.time.as_millis(),
+        report.hashfull,
+        pv.join(" ")
+    );
+    println!("info string tt {}", report.tt);
+}
+
+fn stop_search(search: &mut Option<Search>) {
+    if let Some(search) = search.take() {
+        search.stop.store(true, Ordering::Relaxed);
+        let _ = search.handle.join();
+    }
+}
@@ -9,6 +9,24 @@ impl Position {
         Self { x, y }
     }
 
+    // algebraic coordinates such as "e4"
+    pub fn parse(s: &str) -> Option<Self> {
+        match *s.as_bytes() {
+            [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => Some(Position::new((file - b'a') as i8, (rank - b'1') as i8)),
+            _ => None,
+        }
+    }
+
+
+    pub fn offset(&self, dx: i8, dy: i8) -> Option<Self> {
+        let x = self.x + dx;
+        let y = self.y + dy;
+        if x < 0 || x > 7 || y < 0 || y > 7 {
+            None
+        } else {
+            Some(Position::new(x, y))
+        }
+    }
 
     pub fn up(&self, n: i8) -> Option<Self> {
         if self.y < 7 - n + 1 {
@@ -75,3 +93,9 @@ impl Position {
     }
 }
 
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", (b'a' + self.x as u8) as char, self.y + 1)
+    }
+}
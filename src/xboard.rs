@@ -0,0 +1,351 @@
+use crate::board::{Board, SearchReport, SearchResult};
+use crate::limits::{SearchLimits, MAX_DEPTH};
+use crate::piece::Color;
+use crate::tt::TranspositionTable;
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const ENGINE_NAME: &str = "chess-rs";
+
+enum Event {
+    Input(String),
+    SearchReport(u64, SearchReport),
+    SearchDone(u64, SearchResult),
+}
+
+struct Search {
+    id: u64,
+    stop: Arc<AtomicBool>,
+}
+
+// time control set by level, st and sd, plus the clocks reported by time and otim
+struct Clock {
+    moves_per_control: u32,
+    increment: Duration,
+    time_per_move: Option<Duration>,
+    max_depth: i32,
+    engine_time: Option<Duration>,
+}
+
+struct Engine {
+    board: Board,
+    // side played by the engine, none in force mode
+    engine_color: Option<Color>,
+    clock: Clock,
+    // print thinking output, toggled by post and nopost
+    post: bool,
+    // kept across games and set up positions
+    tt: Arc<Mutex<TranspositionTable>>,
+    search: Option<Search>,
+    next_search_id: u64,
+    events: Sender<Event>,
+}
+
+pub fn run() {
+    let (events, rx_events) = channel::<Event>();
+
+    let input = events.clone();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if input.send(Event::Input(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+        let _ = input.send(Event::Input("quit".to_string()));
+    });
+
+    let mut board = Board::new_classic_game();
+    let tt = board.transposition_table();
+    let mut engine = Engine {
+        board,
+        engine_color: Some(Color::Black),
+        clock: Clock {
+            moves_per_control: 0,
+            increment: Duration::ZERO,
+            time_per_move: None,
+            max_depth: MAX_DEPTH,
+            engine_time: None,
+        },
+        post: false,
+        tt,
+        search: None,
+        next_search_id: 0,
+        events,
+    };
+
+    for event in rx_events {
+        match event {
+            Event::Input(line) => {
+                if !engine.handle_command(&line) {
+                    break;
+                }
+            }
+            Event::SearchReport(id, report) => engine.handle_search_report(id, report),
+            Event::SearchDone(id, result) => engine.handle_search_result(id, result),
+        }
+    }
+
+    engine.cancel_search();
+}
+
+impl Engine {
+    // returns false once the engine should exit
+    fn handle_command(&mut self, line: &str) -> bool {
+        let mut tokens = line.split_whitespace();
+        let command = match tokens.next() {
+            Some(command) => command,
+            None => return true,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" => {}
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "protover" => {
+                println!(
+                    "feature myname=\"{}\" usermove=1 setboard=1 ping=1 playother=1 memory=1 colors=0 sigint=0 sigterm=0 done=1",
+                    ENGINE_NAME
+                );
+            }
+            "new" => {
+                self.cancel_search();
+                self.board = Board::new_classic_game();
+                self.board.set_transposition_table(self.tt.clone());
+                self.tt.lock().unwrap().clear();
+                self.engine_color = Some(Color::Black);
+                self.clock.time_per_move = None;
+                self.clock.max_depth = MAX_DEPTH;
+            }
+            "memory" => {
+                self.cancel_search();
+                match args.first().and_then(|m| m.parse::<usize>().ok()) {
+                    Some(megabytes) if megabytes > 0 => self.board.set_hash_size(megabytes),
+                    _ => println!("Error (bad memory size): {}", line),
+                }
+            }
+            "setboard" => {
+                self.cancel_search();
+                match Board::from_fen(&args.join(" ")) {
+                    Ok(board) => {
+                        self.board = board;
+                        self.board.set_transposition_table(self.tt.clone());
+                    }
+                    Err(e) => println!("tellusererror Illegal position: {}", e),
+                }
+            }
+            "force" | "result" => {
+                self.cancel_search();
+                self.engine_color = None;
+            }
+            "go" => {
+                self.cancel_search();
+                self.engine_color = Some(self.board.current_color());
+                self.start_search();
+            }
+            "playother" => {
+                self.cancel_search();
+                self.engine_color = Some(self.board.current_color().opposite());
+            }
+            "usermove" => {
+                let text = args.first().copied().unwrap_or("");
+                self.handle_user_move(text);
+            }
+            "undo" => {
+                self.cancel_search();
+                if self.board.depth() > 0 {
+                    self.board.pop_move();
+                }
+            }
+            "remove" => {
+                self.cancel_search();
+                for _ in 0..2 {
+                    if self.board.depth() > 0 {
+                        self.board.pop_move();
+                    }
+                }
+            }
+            "level" => {
+                // level MPS BASE INC, with BASE in minutes or minutes:seconds
+                self.clock.moves_per_control = args.first().and_then(|v| v.parse().ok()).unwrap_or(0);
+                self.clock.increment = args
+                    .get(2)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .map_or(Duration::ZERO, Duration::from_secs_f64);
+                self.clock.time_per_move = None;
+            }
+            "st" => {
+                self.clock.time_per_move = args
+                    .first()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .map(Duration::from_secs_f64);
+            }
+            "sd" => {
+                self.clock.max_depth = args.first().and_then(|v| v.parse().ok()).unwrap_or(MAX_DEPTH);
+            }
+            "time" => {
+                // centiseconds left on the engine clock
+                self.clock.engine_time = args
+                    .first()
+                    .and_then(|v| v.parse::<u64>().ok())
+                    .map(|cs| Duration::from_millis(cs * 10));
+            }
+            "otim" => {}
+            "?" => {
+                // the search still answers with its best move so far
+                if let Some(search) = &self.search {
+                    search.stop.store(true, Ordering::Relaxed);
+                }
+            }
+            "ping" => {
+                println!("pong {}", args.first().copied().unwrap_or(""));
+            }
+            "quit" => return false,
+            _ => {
+                // old versions of the protocol send bare moves
+                if self.board.parse_move(command).is_ok() {
+                    self.handle_user_move(command);
+                } else {
+                    println!("Error (unknown command): {}", command);
+                }
+            }
+        }
+
+        true
+    }
+
+    fn handle_user_move(&mut self, text: &str) {
+        let m = match self.board.parse_move(text).or_else(|_| self.board.parse_san(text)) {
+            Ok(m) => m,
+            Err(_) => {
+                println!("Illegal move: {}", text);
+                return;
+            }
+        };
+
+        self.cancel_search();
+        self.board.push_move(m);
+
+        if self.report_game_over() {
+            return;
+        }
+
+        if self.engine_color == Some(self.board.current_color()) {
+            self.start_search();
+        }
+    }
+
+    // thinking output: ply, score, time in centiseconds, nodes and the principal variation
+    fn handle_search_report(&mut self, id: u64, report: SearchReport) {
+        let current = matches!(&self.search, Some(search) if search.id == id);
+        if !self.post || !current {
+            return;
+        }
+
+        // mates are reported as 100000 + moves, as xboard expects
+        let score = match report.score.mate_moves() {
+            Some(moves) if moves > 0 => 100000 + moves,
+            Some(moves) => -100000 + moves,
+            None => report.score.value() as i32,
+        };
+        let pv: Vec<String> = report.pv.iter().map(|m| m.to_string()).collect();
+        println!(
+            "{} {} {} {} {}",
+            report.depth,
+            score,
+            report.time.as_millis() / 10,
+            report.nodes,
+            pv.join(" ")
+        );
+    }
+
+    fn handle_search_result(&mut self, id: u64, result: SearchResult) {
+        // results of cancelled searches are dropped
+        match &self.search {
+            Some(search) if search.id == id => self.search = None,
+            _ => return,
+        }
+
+        match result {
+            SearchResult::BestMove(m) => {
+                println!("move {}", m);
+                self.board.push_move(m);
+                self.report_game_over();
+            }
+            SearchResult::GameOver(_) => {
+                self.report_game_over();
+            }
+        }
+    }
+
+    fn report_game_over(&mut self) -> bool {
+        let status = self.board.game_status();
+        let result = match status.result() {
+            Some(result) => result,
+            None => return false,
+        };
+        println!("{} {{{}}}", result, status);
+        self.engine_color = None;
+        true
+    }
+
+    fn start_search(&mut self) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let id = self.next_search_id;
+        self.next_search_id += 1;
+
+        let mut board = self.board.clone();
+        board.set_transposition_table(self.tt.clone());
+        let limits = self.search_limits();
+        let events = self.events.clone();
+        std::thread::spawn(move || {
+            let result = board.find_best_move(&limits, &thread_stop, |report| {
+                let _ = events.send(Event::SearchReport(id, report.clone()));
+            });
+            let _ = events.send(Event::SearchDone(id, result));
+        });
+
+        self.search = Some(Search { id, stop });
+    }
+
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn search_limits(&self) -> SearchLimits {
+        let mut limits = SearchLimits::depth(self.clock.max_depth);
+        if let Some(time_per_move) = self.clock.time_per_move {
+            limits.movetime = Some(time_per_move);
+            return limits;
+        }
+
+        // the engine's clock belongs to the side to move, xboard only starts searches on its turn
+        match self.board.current_color() {
+            Color::White => {
+                limits.wtime = self.clock.engine_time;
+                limits.winc = self.clock.increment;
+            }
+            Color::Black => {
+                limits.btime = self.clock.engine_time;
+                limits.binc = self.clock.increment;
+            }
+        }
+        if self.clock.moves_per_control > 0 {
+            let played = (self.board.fullmove_number() - 1) % self.clock.moves_per_control;
+            limits.movestogo = Some(self.clock.moves_per_control - played);
+        }
+        limits
+    }
+}
@@ -0,0 +1,115 @@
+use crate::board::CastlingRights;
+use crate::piece::{Color, Piece, Type};
+use crate::utils::Position;
+
+// random keys xor-ed together into a 64 bit position key, see
+// https://www.chessprogramming.org/Zobrist_Hashing
+
+// splitmix64, so the tables can be generated at compile time from a fixed seed
+const fn next(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+struct Keys {
+    // [color][type][x * 8 + y]
+    pieces: [[[u64; 64]; 6]; 2],
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+    side: u64,
+}
+
+const fn generate() -> Keys {
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 6]; 2],
+        castling: [0; 4],
+        en_passant: [0; 8],
+        side: 0,
+    };
+    let mut state = 0x2545_f491_4f6c_dd1d;
+    let mut value;
+
+    let mut color = 0;
+    while color < 2 {
+        let mut t = 0;
+        while t < 6 {
+            let mut cell = 0;
+            while cell < 64 {
+                (state, value) = next(state);
+                keys.pieces[color][t][cell] = value;
+                cell += 1;
+            }
+            t += 1;
+        }
+        color += 1;
+    }
+
+    let mut i = 0;
+    while i < 4 {
+        (state, value) = next(state);
+        keys.castling[i] = value;
+        i += 1;
+    }
+
+    let mut i = 0;
+    while i < 8 {
+        (state, value) = next(state);
+        keys.en_passant[i] = value;
+        i += 1;
+    }
+
+    (_, value) = next(state);
+    keys.side = value;
+    keys
+}
+
+const KEYS: Keys = generate();
+
+pub fn piece(piece: &Piece) -> u64 {
+    let color = match piece.color {
+        Color::White => 0,
+        Color::Black => 1,
+    };
+    let t = match piece.t {
+        Type::Pawn => 0,
+        Type::Bishop => 1,
+        Type::Knight => 2,
+        Type::Rook => 3,
+        Type::Queen => 4,
+        Type::King => 5,
+    };
+    KEYS.pieces[color][t][(piece.position.x * 8 + piece.position.y) as usize]
+}
+
+pub fn castling(rights: &CastlingRights) -> u64 {
+    let mut key = 0;
+    for (i, can_castle) in [
+        rights.white_king_side,
+        rights.white_queen_side,
+        rights.black_king_side,
+        rights.black_queen_side,
+    ]
+    .iter()
+    .enumerate()
+    {
+        if *can_castle {
+            key ^= KEYS.castling[i];
+        }
+    }
+    key
+}
+
+pub fn en_passant(position: Option<Position>) -> u64 {
+    match position {
+        Some(position) => KEYS.en_passant[position.x as usize],
+        None => 0,
+    }
+}
+
+// xor-ed in when black is to move
+pub fn side() -> u64 {
+    KEYS.side
+}